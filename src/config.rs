@@ -1,25 +1,158 @@
-use crate::transit::TransitLine;
+use crate::{
+    display::BackendKind,
+    transit::{ProviderConfig, TransitLine},
+    util::{LocalTz, Staleness},
+};
 use anyhow::Context;
 use log::info;
 use serde::Deserialize;
-use std::fs::File;
+use std::{
+    env,
+    fs::File,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub display_port: String,
-    pub forecast_office: String,
-    pub forecast_gridpoint: (u32, u32),
+    /// Where to send rendered frames. Use `memory` to run without hardware
+    #[serde(default)]
+    pub display_backend: BackendKind,
+    /// SPI device for the display. Only needed for the e-ink backend
+    #[serde(default)]
+    pub display_port: Option<String>,
+    /// Where to get the weather for. NWS resolves this to a forecast
+    /// gridpoint
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// NWS forecast office, e.g. `BOX`. Set this and `forecast_gridpoint`
+    /// to skip looking up the gridpoint from latitude and longitude
+    #[serde(default)]
+    pub forecast_office: Option<String>,
+    #[serde(default)]
+    pub forecast_gridpoint: Option<(u32, u32)>,
+    /// NWS station to take current conditions from, e.g. `KBOS`. Defaults
+    /// to the station nearest the forecast gridpoint
+    #[serde(default)]
+    pub observation_station: Option<String>,
     pub transit_lines: Vec<TransitLine>,
+    /// Where to get transit data from. Defaults to the MBTA
+    #[serde(default)]
+    pub transit_provider: ProviderConfig,
+    /// Address to serve a browser preview of the screen on, e.g.
+    /// `0.0.0.0:8080`. Disabled if omitted
+    #[serde(default)]
+    pub preview_server: Option<SocketAddr>,
+    /// Directory to save the last good API responses in, so there's
+    /// something to show right after a restart. Set to `null` to disable
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: Option<PathBuf>,
+    /// Contact email for the NWS API, which wants one in the User-Agent so
+    /// they can reach out about problems. Overridden by
+    /// `GOLDFINGER_NWS_CONTACT`
+    #[serde(default)]
+    pub nws_contact: Option<String>,
+    /// MBTA v3 API key, for a higher rate limit. Overridden by
+    /// `GOLDFINGER_MBTA_API_KEY`
+    #[serde(default)]
+    pub mbta_api_key: Option<String>,
+    /// Stream transit predictions from the MBTA as they change, instead of
+    /// polling
+    #[serde(default)]
+    pub transit_streaming: bool,
+    /// When to flag the forecast as old on screen, and when to hide it
+    #[serde(default = "default_weather_staleness")]
+    pub weather_staleness: Staleness,
+    /// When to flag transit predictions as old on screen, and when to hide
+    /// them
+    #[serde(default = "default_transit_staleness")]
+    pub transit_staleness: Staleness,
+    /// Timezone to show times in, e.g. `America/New_York`. Defaults to the
+    /// system timezone
+    #[serde(default)]
+    pub timezone: LocalTz,
 }
 
 impl Config {
-    const PATH: &'static str = "./config.json";
+    pub const DEFAULT_PATH: &'static str = "./config.json";
 
-    /// Load config from file
-    pub fn load() -> anyhow::Result<Self> {
-        info!("Loading config from `{}`", Self::PATH);
-        let file = File::open(Self::PATH)?;
+    /// Load config from file, then apply overrides from the environment
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut config = Self::parse(path)?;
+        config.load_env(|variable| env::var(variable).ok());
+        Ok(config)
+    }
+
+    /// Load config from file, without looking at the environment
+    fn parse(path: &Path) -> anyhow::Result<Self> {
+        info!("Loading config from {path:?}");
+        let file = File::open(path)
+            .context(format!("Error opening config file {path:?}"))?;
         serde_json::from_reader(file)
-            .context(format!("Error parsing config file {}", Self::PATH))
+            .context(format!("Error parsing config file {path:?}"))
+    }
+
+    /// Override secrets from environment variables, so they don't have to be
+    /// stored in the config file. `get_var` looks up a variable
+    fn load_env(&mut self, get_var: impl Fn(&str) -> Option<String>) {
+        for (variable, field) in [
+            ("GOLDFINGER_NWS_CONTACT", &mut self.nws_contact),
+            ("GOLDFINGER_MBTA_API_KEY", &mut self.mbta_api_key),
+        ] {
+            if let Some(value) = get_var(variable) {
+                info!("Loaded {variable} from environment");
+                *field = Some(value);
+            }
+        }
+    }
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    Some("./cache".into())
+}
+
+fn default_weather_staleness() -> Staleness {
+    Staleness {
+        stale_minutes: 30,
+        expire_minutes: 6 * 60,
+    }
+}
+
+/// Predictions go bad fast, since buses move
+fn default_transit_staleness() -> Staleness {
+    Staleness {
+        stale_minutes: 5,
+        expire_minutes: 30,
+    }
+}
+
+/// Load the fixture config, as a starting point for tests. The environment
+/// is ignored, so a developer's secrets don't leak into tests
+#[cfg(test)]
+pub fn test_config() -> Config {
+    Config::parse(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/config.json"
+    )))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Secrets from the environment override the config file
+    #[test]
+    fn test_load_env() {
+        let mut config = test_config();
+        config.nws_contact = Some("file@example.com".into());
+        config.mbta_api_key = Some("file".into());
+        config.load_env(|variable| {
+            (variable == "GOLDFINGER_MBTA_API_KEY").then(|| "hunter2".into())
+        });
+        assert_eq!(config.nws_contact.as_deref(), Some("file@example.com"));
+        assert_eq!(config.mbta_api_key.as_deref(), Some("hunter2"));
     }
 }
@@ -1,40 +1,33 @@
+#[cfg(target_os = "linux")]
+mod eink;
+mod frame;
+mod memory;
+
+pub use frame::Frame;
+
 use crate::config::Config;
 use anyhow::{anyhow, Context};
-use display_interface::DisplayError;
-use display_interface_spi::SPIInterface;
 use embedded_graphics::{
-    geometry::Point,
+    geometry::{Dimensions, Point, Size},
+    primitives::{Circle, Primitive, PrimitiveStyle, Rectangle},
     text::{Alignment, Baseline, LineHeight, TextStyleBuilder},
     Drawable,
 };
-use linux_embedded_hal::{
-    spidev::{SpiModeFlags, SpidevOptions},
-    sysfs_gpio::Direction,
-    Delay, SpidevDevice, SysfsPin,
-};
 use log::{error, info, trace};
+use serde::Deserialize;
 use std::time::{Duration, Instant};
 use u8g2_fonts::{fonts, U8g2TextStyle};
 use weact_studio_epd::{
     graphics::{Display213BlackWhite, DisplayRotation},
-    Color, WeActStudio213BlackWhiteDriver,
+    Color,
 };
 
-const PIN_BUSY: u64 = 17; // GPIO/BCM 17, pin 11
-const PIN_DC: u64 = 22; // GPIO/BCM 22, pin 15
-const PIN_RESET: u64 = 27; // GPIO/BCM 27, pin 13
-
-type Text<'a> = embedded_graphics::text::Text<'a, U8g2TextStyle<Color>>;
+pub type Text<'a> = embedded_graphics::text::Text<'a, U8g2TextStyle<Color>>;
 
 /// Manage text state calculation and hardware communication
 pub struct Display {
     // Hardware state
-    device: WeActStudio213BlackWhiteDriver<
-        SPIInterface<SpidevDevice, SysfsPin>,
-        SysfsPin,
-        SysfsPin,
-        Delay,
-    >,
+    backend: Box<dyn Backend>,
     display: Display213BlackWhite,
 
     // Logical state
@@ -56,44 +49,43 @@ impl Display {
     /// How frequently to do a full (as opposed to partial) update on the
     /// screen? The full update cleans up artifacts that accumulate over time.
     const FULL_UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    /// Size of the refresh indicator dot
+    const INDICATOR_DIAMETER: u32 = 5;
+    /// Horizontal space between the edge of a badge and its text
+    pub const BADGE_PADDING: i32 = 2;
 
     pub fn new(config: &Config) -> anyhow::Result<Self> {
-        let mut spi =
-            SpidevDevice::open(&config.display_port).context("SPI device")?;
-        let options = SpidevOptions::new()
-            .bits_per_word(8)
-            .max_speed_hz(1_000_000)
-            .mode(SpiModeFlags::SPI_MODE_0)
-            .build();
-        spi.configure(&options).context("SPI configuration")?;
-
-        let reset = init_pin(PIN_RESET, Direction::Out)
-            .context("Initializing pin Reset")?;
-        let dc =
-            init_pin(PIN_DC, Direction::Out).context("Initializing pin D/C")?;
-        let busy = init_pin(PIN_BUSY, Direction::In)
-            .context("Initializing pin Busy")?;
-        let spi_interface = SPIInterface::new(spi, dc);
-
-        let mut controller = WeActStudio213BlackWhiteDriver::new(
-            spi_interface,
-            busy,
-            reset,
-            Delay,
-        );
-        controller.init().map_err(map_error)?;
-        info!("Display controller initialized");
+        let backend: Box<dyn Backend> = match config.display_backend {
+            #[cfg(target_os = "linux")]
+            BackendKind::Eink => {
+                let port = config.display_port.as_deref().ok_or_else(|| {
+                    anyhow!("`display_port` is required for the e-ink backend")
+                })?;
+                Box::new(eink::EinkBackend::new(port)?)
+            }
+            #[cfg(not(target_os = "linux"))]
+            BackendKind::Eink => {
+                return Err(anyhow!(
+                    "The e-ink backend is only supported on Linux"
+                ));
+            }
+            BackendKind::Memory => Box::new(memory::MemoryBackend::default()),
+        };
+        info!("Using {:?} display backend", config.display_backend);
+        Ok(Self::with_backend(backend))
+    }
 
+    fn with_backend(backend: Box<dyn Backend>) -> Self {
         let mut display = Display213BlackWhite::new();
         display.set_rotation(DisplayRotation::Rotate90);
 
-        Ok(Self {
-            device: controller,
+        Self {
+            backend,
             display,
             text_buffer: Vec::new(),
             // Ensure we always start with a full update
             last_full_update: Instant::now() - Self::FULL_UPDATE_INTERVAL,
-        })
+        }
     }
 
     /// Draw some text to the screen buffer
@@ -101,6 +93,39 @@ impl Display {
         text.draw(&mut self.display).expect("Infallible")
     }
 
+    /// Draw some text inverted (white on black) to call attention to it
+    pub fn draw_badge(&mut self, text: &Text) {
+        Rectangle::new(
+            text.bounding_box().top_left - Point::new(Self::BADGE_PADDING, 0),
+            text.bounding_box().size
+                + Size::new(2 * Self::BADGE_PADDING as u32, 0),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Color::Black))
+        .draw(&mut self.display)
+        .expect("Infallible");
+        let mut inverted = text.clone();
+        inverted.character_style.text_color = Some(Color::White);
+        inverted.draw(&mut self.display).expect("Infallible");
+    }
+
+    /// Draw a small dot to show that data is being refreshed
+    pub fn draw_indicator(&mut self, center: Point) {
+        Circle::with_center(center, Self::INDICATOR_DIAMETER)
+            .into_styled(PrimitiveStyle::with_fill(Color::Black))
+            .draw(&mut self.display)
+            .expect("Infallible")
+    }
+
+    /// Get the frame that's currently on the screen, i.e. the last one passed
+    /// to the backend
+    pub fn frame(&self) -> Frame {
+        // Take the backend's word for it if we can
+        match self.backend.frame() {
+            Some(frame) => frame.clone(),
+            None => Frame::from_buffer(&self.text_buffer),
+        }
+    }
+
     /// Draw current text buffer to the screen, if it's changed
     pub fn draw(&mut self) -> anyhow::Result<()> {
         // If anything changed, update the screen
@@ -109,10 +134,14 @@ impl Display {
             if now - self.last_full_update > Self::FULL_UPDATE_INTERVAL {
                 info!("Updating display (full)");
                 self.last_full_update = now;
-                self.device.full_update(&self.display).map_err(map_error)?;
+                self.backend
+                    .full_update(&self.display)
+                    .context("Error updating display")?;
             } else {
                 trace!("Updating display (fast)");
-                self.device.fast_update(&self.display).map_err(map_error)?;
+                self.backend
+                    .fast_update(&self.display)
+                    .context("Error updating display")?;
             }
             // Store this buffer so we can check if it's changed later
             self.text_buffer = self.display.buffer().to_owned();
@@ -132,7 +161,7 @@ impl Drop for Display {
         info!("Clearing display for shutdown");
 
         self.display.clear(Color::White);
-        let result = self.device.full_update(&self.display);
+        let result = self.backend.full_update(&self.display);
         if let Err(error) = result {
             error!("Failed to clear display on shutdown: {error:?}")
         }
@@ -140,9 +169,43 @@ impl Drop for Display {
     }
 }
 
+/// Which backend should rendered frames be pushed to?
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// Physical e-ink display. Only available on Linux
+    #[default]
+    Eink,
+    /// In-memory framebuffer. Useful for developing without a Pi
+    Memory,
+}
+
+/// A destination for rendered frames. [Display] handles all the drawing and
+/// change detection, so a backend just has to put a finished frame somewhere.
+pub trait Backend {
+    /// Push a frame using a full refresh. This is slow but cleans up artifacts
+    fn full_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()>;
+
+    /// Push a frame using a fast refresh
+    fn fast_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()>;
+
+    /// The last frame pushed, if the backend keeps a copy. Hardware can't be
+    /// read back, so by default it doesn't
+    fn frame(&self) -> Option<&Frame> {
+        None
+    }
+}
+
 /// Available font sizes
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FontSize {
+    Small,
     Medium,
     Large,
 }
@@ -150,6 +213,9 @@ pub enum FontSize {
 impl FontSize {
     pub fn font(&self) -> U8g2TextStyle<Color> {
         match self {
+            FontSize::Small => {
+                U8g2TextStyle::new(fonts::u8g2_font_spleen6x12_me, Color::Black)
+            }
             FontSize::Medium => U8g2TextStyle::new(
                 fonts::u8g2_font_spleen12x24_me,
                 Color::Black,
@@ -162,8 +228,9 @@ impl FontSize {
     }
 
     /// Line height (in pixels) to get compact text
-    fn line_height(&self) -> u32 {
+    pub fn line_height(&self) -> u32 {
         match self {
+            FontSize::Small => 12,
             FontSize::Medium => 19,
             FontSize::Large => 40,
         }
@@ -185,22 +252,3 @@ pub fn text(
         .build();
     Text::with_text_style(text, position.into(), character_style, text_style)
 }
-
-/// Initialize a GPIO pin
-fn init_pin(pin_num: u64, direction: Direction) -> anyhow::Result<SysfsPin> {
-    let pin = SysfsPin::new(pin_num);
-    pin.export().context("Error exporting pin")?;
-    while !pin.is_exported() {}
-    pin.set_direction(direction)
-        .context("Error setting pin direction")?;
-    if matches!(direction, Direction::Out) {
-        pin.set_value(1).context("Error enabling pin")?;
-    }
-    Ok(pin)
-}
-
-/// The error type from the driver doesn't implement Error so we have to map
-/// manually
-fn map_error(error: DisplayError) -> anyhow::Error {
-    anyhow!("{error:?}")
-}
@@ -0,0 +1,93 @@
+use crate::display::Backend;
+use anyhow::{anyhow, Context};
+use display_interface::DisplayError;
+use display_interface_spi::SPIInterface;
+use linux_embedded_hal::{
+    spidev::{SpiModeFlags, SpidevOptions},
+    sysfs_gpio::Direction,
+    Delay, SpidevDevice, SysfsPin,
+};
+use log::info;
+use weact_studio_epd::{
+    graphics::Display213BlackWhite, WeActStudio213BlackWhiteDriver,
+};
+
+const PIN_BUSY: u64 = 17; // GPIO/BCM 17, pin 11
+const PIN_DC: u64 = 22; // GPIO/BCM 22, pin 15
+const PIN_RESET: u64 = 27; // GPIO/BCM 27, pin 13
+
+/// The real deal: a WeAct Studio 2.13" e-ink panel over SPI
+pub struct EinkBackend {
+    device: WeActStudio213BlackWhiteDriver<
+        SPIInterface<SpidevDevice, SysfsPin>,
+        SysfsPin,
+        SysfsPin,
+        Delay,
+    >,
+}
+
+impl EinkBackend {
+    pub fn new(port: &str) -> anyhow::Result<Self> {
+        let mut spi = SpidevDevice::open(port).context("SPI device")?;
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(1_000_000)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        spi.configure(&options).context("SPI configuration")?;
+
+        let reset = init_pin(PIN_RESET, Direction::Out)
+            .context("Initializing pin Reset")?;
+        let dc =
+            init_pin(PIN_DC, Direction::Out).context("Initializing pin D/C")?;
+        let busy = init_pin(PIN_BUSY, Direction::In)
+            .context("Initializing pin Busy")?;
+        let spi_interface = SPIInterface::new(spi, dc);
+
+        let mut device = WeActStudio213BlackWhiteDriver::new(
+            spi_interface,
+            busy,
+            reset,
+            Delay,
+        );
+        device.init().map_err(map_error)?;
+        info!("Display controller initialized");
+
+        Ok(Self { device })
+    }
+}
+
+impl Backend for EinkBackend {
+    fn full_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()> {
+        self.device.full_update(frame).map_err(map_error)
+    }
+
+    fn fast_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()> {
+        self.device.fast_update(frame).map_err(map_error)
+    }
+}
+
+/// Initialize a GPIO pin
+fn init_pin(pin_num: u64, direction: Direction) -> anyhow::Result<SysfsPin> {
+    let pin = SysfsPin::new(pin_num);
+    pin.export().context("Error exporting pin")?;
+    while !pin.is_exported() {}
+    pin.set_direction(direction)
+        .context("Error setting pin direction")?;
+    if matches!(direction, Direction::Out) {
+        pin.set_value(1).context("Error enabling pin")?;
+    }
+    Ok(pin)
+}
+
+/// The error type from the driver doesn't implement Error so we have to map
+/// manually
+fn map_error(error: DisplayError) -> anyhow::Error {
+    anyhow!("{error:?}")
+}
@@ -0,0 +1,190 @@
+use anyhow::Context;
+use std::{fs, path::Path};
+use weact_studio_epd::graphics::Display213BlackWhite;
+
+/// A snapshot of the visible area of the screen, in the same orientation that
+/// the screen is viewed in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Row-major pixels. `true` is black, `false` is white
+    pixels: Vec<bool>,
+}
+
+impl Frame {
+    /// Visible width of the screen, in pixels
+    pub const WIDTH: u32 = 250;
+    /// Visible height of the screen, in pixels
+    pub const HEIGHT: u32 = 122;
+
+    /// Bytes per row in the raw display buffer. The buffer is stored unrotated,
+    /// so a row in the buffer is a column on the screen
+    const BUFFER_ROW_BYTES: usize = 128 / 8;
+
+    /// Extract the visible frame from a raw [Display213BlackWhite] buffer. The
+    /// buffer is rotated 90° relative to the screen, and the first 6 rows (the
+    /// last 6 bits of each buffer row) aren't visible. An empty buffer is
+    /// treated as blank, since that's what the screen starts as.
+    pub fn from_buffer(buffer: &[u8]) -> Self {
+        let mut pixels = vec![false; (Self::WIDTH * Self::HEIGHT) as usize];
+        if buffer.is_empty() {
+            return Self { pixels };
+        }
+        debug_assert_eq!(
+            buffer.len(),
+            Display213BlackWhite::new().buffer().len()
+        );
+
+        let buffer_width = Self::BUFFER_ROW_BYTES * 8;
+        let hidden_rows = buffer_width - Self::HEIGHT as usize;
+        for y in 0..Self::HEIGHT as usize {
+            for x in 0..Self::WIDTH as usize {
+                // Reverse the Rotate90 transformation
+                let buffer_x = buffer_width - 1 - (y + hidden_rows);
+                let buffer_y = x;
+                let byte =
+                    buffer[buffer_x / 8 + Self::BUFFER_ROW_BYTES * buffer_y];
+                let white = byte & (0x80 >> (buffer_x % 8)) != 0;
+                pixels[y * Self::WIDTH as usize + x] = !white;
+            }
+        }
+        Self { pixels }
+    }
+
+    /// Encode this frame as a 1-bit grayscale PNG
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut encoder =
+            png::Encoder::new(&mut bytes, Self::WIDTH, Self::HEIGHT);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        let mut writer =
+            encoder.write_header().context("Error writing PNG header")?;
+
+        // Pack each row into bits, MSB first. In grayscale, 1 is white
+        let row_bytes = Self::WIDTH.div_ceil(8) as usize;
+        let mut data = vec![0u8; row_bytes * Self::HEIGHT as usize];
+        for (i, row) in self.pixels.chunks(Self::WIDTH as usize).enumerate() {
+            for (x, black) in row.iter().enumerate() {
+                if !black {
+                    data[i * row_bytes + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        writer
+            .write_image_data(&data)
+            .context("Error writing PNG data")?;
+        writer.finish().context("Error finishing PNG")?;
+        Ok(bytes)
+    }
+
+    /// Write this frame to a PNG file
+    pub fn write_png(&self, path: &Path) -> anyhow::Result<()> {
+        let bytes = self.to_png()?;
+        fs::write(path, bytes)
+            .with_context(|| format!("Error writing frame to {path:?}"))
+    }
+}
+
+#[cfg(test)]
+impl Frame {
+    /// Decode a frame from a PNG, as written by [Self::to_png]
+    pub fn from_png(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        // Expand 1-bit grayscale to one byte per pixel
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader.next_frame(&mut buffer)?;
+        anyhow::ensure!(
+            (info.width, info.height) == (Self::WIDTH, Self::HEIGHT)
+                && info.color_type == png::ColorType::Grayscale,
+            "Expected {}x{} grayscale image, got {}x{} {:?}",
+            Self::WIDTH,
+            Self::HEIGHT,
+            info.width,
+            info.height,
+            info.color_type,
+        );
+        let pixels = buffer[..info.buffer_size()]
+            .iter()
+            .map(|value| *value < 128)
+            .collect();
+        Ok(Self { pixels })
+    }
+
+    /// Compare this frame to an expected one. If they differ, return the
+    /// number of mismatched pixels, and an RGB PNG highlighting them. Red
+    /// pixels should be black but aren't; blue pixels shouldn't be but are.
+    pub fn diff(
+        &self,
+        expected: &Self,
+    ) -> anyhow::Result<Option<(usize, Vec<u8>)>> {
+        let mut mismatches = 0;
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for (actual, expected) in self.pixels.iter().zip(&expected.pixels) {
+            let rgb = match (actual, expected) {
+                (true, true) => [0, 0, 0],
+                (false, false) => [255, 255, 255],
+                (false, true) => [255, 0, 0],
+                (true, false) => [0, 0, 255],
+            };
+            if actual != expected {
+                mismatches += 1;
+            }
+            data.extend(rgb);
+        }
+        if mismatches == 0 {
+            return Ok(None);
+        }
+
+        let mut bytes = Vec::new();
+        let mut encoder =
+            png::Encoder::new(&mut bytes, Self::WIDTH, Self::HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(Some((mismatches, bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::{
+        prelude::{Point, Primitive, Size},
+        primitives::{PrimitiveStyle, Rectangle},
+        Drawable,
+    };
+    use weact_studio_epd::{graphics::DisplayRotation, Color};
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut display = Display213BlackWhite::new();
+        display.set_rotation(DisplayRotation::Rotate90);
+        // Top-left corner of the *visible* area
+        Rectangle::new(Point::new(0, 6), Size::new(3, 2))
+            .into_styled(PrimitiveStyle::with_fill(Color::Black))
+            .draw(&mut display)
+            .unwrap();
+
+        let frame = Frame::from_buffer(display.buffer());
+        let black: Vec<usize> = frame
+            .pixels
+            .iter()
+            .enumerate()
+            .filter(|(_, black)| **black)
+            .map(|(i, _)| i)
+            .collect();
+        let width = Frame::WIDTH as usize;
+        assert_eq!(black, vec![0, 1, 2, width, width + 1, width + 2]);
+
+        let decoded = Frame::from_png(&frame.to_png().unwrap()).unwrap();
+        assert_eq!(decoded, frame);
+        assert!(decoded.diff(&frame).unwrap().is_none());
+        let (mismatches, _) =
+            decoded.diff(&Frame::from_buffer(&[])).unwrap().unwrap();
+        assert_eq!(mismatches, 6);
+    }
+}
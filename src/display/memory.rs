@@ -0,0 +1,79 @@
+use crate::display::{Backend, Frame};
+use log::debug;
+use weact_studio_epd::graphics::Display213BlackWhite;
+
+/// A backend with no hardware behind it, for running anywhere. Updates are
+/// counted and logged, and the last frame is kept so it can be checked or
+/// previewed.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    /// Number of updates that have been pushed, for debugging
+    updates: usize,
+    /// The last frame pushed. `None` until the first update
+    frame: Option<Frame>,
+}
+
+impl MemoryBackend {
+    fn update(&mut self, kind: &str, frame: &Display213BlackWhite) {
+        self.updates += 1;
+        self.frame = Some(Frame::from_buffer(frame.buffer()));
+        debug!("Updating memory framebuffer ({kind} #{})", self.updates);
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn full_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()> {
+        self.update("full", frame);
+        Ok(())
+    }
+
+    fn fast_update(
+        &mut self,
+        frame: &Display213BlackWhite,
+    ) -> anyhow::Result<()> {
+        self.update("fast", frame);
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::{
+        prelude::{Point, Primitive, Size},
+        primitives::{PrimitiveStyle, Rectangle},
+        Drawable,
+    };
+    use weact_studio_epd::{graphics::DisplayRotation, Color};
+
+    /// The last frame pushed is kept, whichever kind of update it was
+    #[test]
+    fn test_frame() {
+        let mut backend = MemoryBackend::default();
+        assert_eq!(backend.frame(), None);
+
+        let mut display = Display213BlackWhite::new();
+        display.set_rotation(DisplayRotation::Rotate90);
+        display.clear(Color::White);
+        backend.full_update(&display).unwrap();
+        let blank = Frame::from_buffer(display.buffer());
+        assert_eq!(backend.frame(), Some(&blank));
+
+        Rectangle::new(Point::new(10, 10), Size::new(20, 5))
+            .into_styled(PrimitiveStyle::with_fill(Color::Black))
+            .draw(&mut display)
+            .unwrap();
+        backend.fast_update(&display).unwrap();
+        let frame = backend.frame().unwrap();
+        assert_ne!(frame, &blank);
+        assert_eq!(frame, &Frame::from_buffer(display.buffer()));
+        assert_eq!(backend.updates, 2);
+    }
+}
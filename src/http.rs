@@ -0,0 +1,313 @@
+use anyhow::Context;
+use std::{fmt::Debug, io::Read, time::Duration};
+use ureq::Agent;
+
+/// An HTTP response, with the body read fully into memory
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Is the status code 2xx?
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Get the value of a header. The name is case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP response whose body is read as it arrives, for endpoints that
+/// keep the connection open
+pub struct StreamResponse {
+    pub status: u16,
+    pub body: Box<dyn Read + Send>,
+}
+
+impl StreamResponse {
+    /// Is the status code 2xx?
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Something that can make HTTP requests. This is the seam between
+/// [ApiFetcher](crate::util::ApiFetcher) and the network, so fetches can be
+/// stubbed out in tests.
+pub trait Transport: Debug + Send + Sync {
+    /// Make a GET request with some extra headers. A non-2xx status is *not*
+    /// an error here; errors are reserved for failing to get a response at
+    /// all (DNS, timeout, etc.)
+    fn get(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response>;
+
+    /// Make a GET request and hand back the body without waiting for it to
+    /// finish, e.g. for server-sent events. Reading the body fails once the
+    /// connection has been open for `max_age`
+    fn get_stream(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        max_age: Duration,
+    ) -> anyhow::Result<StreamResponse>;
+}
+
+/// Real transport, backed by ureq
+#[derive(Debug)]
+pub struct UreqTransport {
+    agent: Agent,
+}
+
+impl UreqTransport {
+    /// Give up on a request after this long, so a hung connection doesn't
+    /// hold a fetch open forever
+    const TIMEOUT: Duration = Duration::from_secs(30);
+}
+
+impl Default for UreqTransport {
+    fn default() -> Self {
+        let agent = Agent::config_builder()
+            // Status codes are handled by the caller
+            .http_status_as_error(false)
+            .timeout_global(Some(Self::TIMEOUT))
+            .build()
+            .into();
+        Self { agent }
+    }
+}
+
+impl Transport for UreqTransport {
+    fn get(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response> {
+        let mut request = self.agent.get(url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut response = request.call()?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                // Non-ASCII header values aren't anything we care about
+                Some((
+                    name.as_str().to_owned(),
+                    value.to_str().ok()?.to_owned(),
+                ))
+            })
+            .collect();
+        let body = response
+            .body_mut()
+            .read_to_vec()
+            .context("Error reading response body")?;
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn get_stream(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        max_age: Duration,
+    ) -> anyhow::Result<StreamResponse> {
+        // The global timeout would cut off a healthy stream, so only bound
+        // getting the response started, and the body by its max age
+        let mut request = self
+            .agent
+            .get(url)
+            .config()
+            .timeout_global(None)
+            .timeout_connect(Some(Self::TIMEOUT))
+            .timeout_recv_response(Some(Self::TIMEOUT))
+            .timeout_recv_body(Some(max_age))
+            .build();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = request.call()?;
+        Ok(StreamResponse {
+            status: response.status().as_u16(),
+            body: Box::new(response.into_body().into_reader()),
+        })
+    }
+}
+
+#[cfg(test)]
+pub use stub::{Stub, StubTransport};
+
+#[cfg(test)]
+mod stub {
+    use super::*;
+    use anyhow::anyhow;
+    use std::{
+        collections::VecDeque,
+        io::Cursor,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    type Headers = Vec<(String, String)>;
+
+    /// Transport that serves canned responses instead of hitting the network.
+    /// Responses are served in the order they were queued; once the queue is
+    /// down to its last entry, that one is repeated forever.
+    #[derive(Clone, Debug, Default)]
+    pub struct StubTransport {
+        stubs: Arc<Mutex<VecDeque<Stub>>>,
+        /// URL and headers of every request made, in order
+        requests: Arc<Mutex<Vec<(String, Headers)>>>,
+    }
+
+    /// A single canned response
+    #[derive(Clone, Debug)]
+    pub struct Stub {
+        /// Block for this long before responding
+        latency: Duration,
+        /// `Err` simulates a network failure
+        result: Result<Response, String>,
+    }
+
+    impl StubTransport {
+        pub fn new(stubs: impl IntoIterator<Item = Stub>) -> Self {
+            let transport = Self::default();
+            for stub in stubs {
+                transport.push(stub);
+            }
+            transport
+        }
+
+        /// Queue a response
+        pub fn push(&self, stub: Stub) {
+            self.stubs.lock().unwrap().push_back(stub);
+        }
+
+        /// URLs of all requests made so far
+        pub fn requests(&self) -> Vec<String> {
+            let requests = self.requests.lock().unwrap();
+            requests.iter().map(|(url, _)| url.clone()).collect()
+        }
+
+        /// Headers of all requests made so far
+        pub fn request_headers(&self) -> Vec<Headers> {
+            let requests = self.requests.lock().unwrap();
+            requests
+                .iter()
+                .map(|(_, headers)| headers.clone())
+                .collect()
+        }
+
+        /// Record a request and serve the next stub for it
+        fn next(
+            &self,
+            url: &str,
+            headers: &[(&str, &str)],
+        ) -> anyhow::Result<Response> {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+                .collect();
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_owned(), headers));
+            let stub = {
+                let mut stubs = self.stubs.lock().unwrap();
+                if stubs.len() > 1 {
+                    stubs.pop_front()
+                } else {
+                    stubs.front().cloned()
+                }
+            }
+            .ok_or_else(|| anyhow!("No stubbed response for {url}"))?;
+
+            thread::sleep(stub.latency);
+            stub.result.map_err(|message| anyhow!(message))
+        }
+    }
+
+    impl Transport for StubTransport {
+        fn get(
+            &self,
+            url: &str,
+            headers: &[(&str, &str)],
+        ) -> anyhow::Result<Response> {
+            self.next(url, headers)
+        }
+
+        /// Serve the stubbed body all at once, as if the server sent it and
+        /// hung up
+        fn get_stream(
+            &self,
+            url: &str,
+            headers: &[(&str, &str)],
+            _max_age: Duration,
+        ) -> anyhow::Result<StreamResponse> {
+            let response = self.next(url, headers)?;
+            Ok(StreamResponse {
+                status: response.status,
+                body: Box::new(Cursor::new(response.body)),
+            })
+        }
+    }
+
+    impl Stub {
+        /// 200 response with a body
+        pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+            Self::status(200, body)
+        }
+
+        /// Response with an arbitrary status code
+        pub fn status(status: u16, body: impl Into<Vec<u8>>) -> Self {
+            Self {
+                latency: Duration::ZERO,
+                result: Ok(Response {
+                    status,
+                    headers: Vec::new(),
+                    body: body.into(),
+                }),
+            }
+        }
+
+        /// Fail to get a response at all
+        pub fn error(message: &str) -> Self {
+            Self {
+                latency: Duration::ZERO,
+                result: Err(message.to_owned()),
+            }
+        }
+
+        /// Add a response header
+        pub fn header(mut self, name: &str, value: &str) -> Self {
+            if let Ok(response) = &mut self.result {
+                response
+                    .headers
+                    .push((name.to_ascii_lowercase(), value.to_owned()));
+            }
+            self
+        }
+
+        /// Delay the response
+        pub fn latency(mut self, latency: Duration) -> Self {
+            self.latency = latency;
+            self
+        }
+    }
+}
@@ -1,23 +1,31 @@
 mod config;
 mod display;
+mod http;
+mod preview;
 mod transit;
 mod util;
 mod weather;
 
 use crate::{
     config::Config,
-    display::{text, Display, FontSize},
-    transit::Transit,
-    weather::Weather,
+    display::{text, BackendKind, Display, FontSize, Text},
+    http::{Transport, UreqTransport},
+    preview::{PreviewServer, PreviewState, Status},
+    transit::{Predictions, Transit},
+    util::{Freshness, LocalTz, Staleness},
+    weather::{Forecast, Weather},
 };
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
 use embedded_graphics::{
     geometry::AnchorX,
     prelude::{Dimensions, Point},
     text::Alignment,
 };
-use log::{info, trace, warn, LevelFilter};
+use log::{debug, info, trace, warn, LevelFilter};
 use std::{
+    env,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -36,7 +44,27 @@ fn main() -> anyhow::Result<()> {
         .parse_default_env()
         .init();
 
-    let mut controller = Controller::new()?;
+    let args = Args::parse()?;
+    let mut config = Config::load(&args.config)?;
+
+    if let Some(path) = &args.render_once {
+        // A one-off render should never touch the hardware, or start
+        // anything long-running. The daemon may already hold the preview
+        // port
+        config.display_backend = BackendKind::Memory;
+        config.preview_server = None;
+        config.transit_streaming = false;
+        let mut controller =
+            Controller::new(&config, Arc::new(UreqTransport::default()))?;
+        return controller.render_once(
+            path,
+            args.fixtures.as_deref(),
+            args.now.unwrap_or_else(Utc::now),
+        );
+    }
+
+    let mut controller =
+        Controller::new(&config, Arc::new(UreqTransport::default()))?;
     let should_run = Arc::new(AtomicBool::new(true));
 
     let r = should_run.clone();
@@ -55,99 +83,766 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Command line arguments
+#[derive(Debug)]
+struct Args {
+    /// Path to the config file
+    config: PathBuf,
+    /// Render a single frame to this PNG file, then exit
+    render_once: Option<PathBuf>,
+    /// With --render-once, load API data from recorded fixtures in this
+    /// directory instead of fetching it
+    fixtures: Option<PathBuf>,
+    /// With --render-once, render as if it's this time instead of now
+    now: Option<DateTime<Utc>>,
+}
+
+impl Args {
+    const USAGE: &'static str = "Usage: goldfinger [--config PATH] \
+        [--render-once OUT.png [--fixtures DIR] [--now RFC3339]]";
+
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self {
+            config: Config::DEFAULT_PATH.into(),
+            render_once: None,
+            fixtures: None,
+            now: None,
+        };
+        let mut iter = env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next().ok_or_else(|| {
+                    anyhow!("Missing value for {arg}\n{}", Self::USAGE)
+                })
+            };
+            match arg.as_str() {
+                "--config" => args.config = value()?.into(),
+                "--render-once" => args.render_once = Some(value()?.into()),
+                "--fixtures" => args.fixtures = Some(value()?.into()),
+                "--now" => {
+                    let now = value()?;
+                    let now = DateTime::parse_from_rfc3339(&now)
+                        .with_context(|| format!("Invalid --now: {now}"))?;
+                    args.now = Some(now.into());
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown argument {arg}\n{}",
+                        Self::USAGE
+                    ))
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
 /// Main controller class
 struct Controller {
     display: Display,
     weather: Weather,
     transit: Transit,
+    weather_staleness: Staleness,
+    transit_staleness: Staleness,
+    timezone: LocalTz,
+    preview: Option<PreviewServer>,
 }
 
 impl Controller {
     /// Number of weather periods we can show at once
     const WEATHER_PERIODS: usize = 4;
+    /// Everything left of this belongs to the weather column. The hourly
+    /// rows are 14 characters of medium text
+    const TRANSIT_LEFT: i32 = 170;
 
-    fn new() -> anyhow::Result<Self> {
-        let config = Config::load()?;
-        let display = Display::new(&config)?;
-        let weather = Weather::new(&config);
-        let transit = Transit::new(&config);
+    fn new(
+        config: &Config,
+        transport: Arc<dyn Transport>,
+    ) -> anyhow::Result<Self> {
+        let display = Display::new(config)?;
+        let weather = Weather::new(config, Arc::clone(&transport))?;
+        let transit = Transit::new(config, transport)?;
+        let preview = config
+            .preview_server
+            .map(PreviewServer::start)
+            .transpose()?;
         Ok(Self {
             display,
             weather,
             transit,
+            weather_staleness: config.weather_staleness,
+            transit_staleness: config.transit_staleness,
+            timezone: config.timezone,
+            preview,
         })
     }
 
     fn tick(&mut self) -> anyhow::Result<()> {
         trace!("Running display tick");
 
-        self.draw();
+        let now = Utc::now();
+        let (forecast, predictions) = self.draw(now);
 
         // Redraw if anything changed
         self.display.draw()?;
+
+        if let Some(preview) = &self.preview {
+            preview.update(PreviewState {
+                frame: self.display.frame(),
+                forecast: forecast.map(|(forecast, _)| forecast),
+                predictions,
+                status: Status {
+                    weather: self.weather.status(),
+                    transit: self.transit.status(),
+                },
+            });
+        }
         Ok(())
     }
 
-    /// Draw screen contents to the buffer (but don't update the hardware)
-    fn draw(&mut self) {
-        // Weather
-        if let Some(forecast) = self.weather.forecast() {
-            let now = forecast.now();
+    /// Load data (from fixtures or the live APIs), draw a single frame, and
+    /// write it to a PNG file
+    fn render_once(
+        &mut self,
+        path: &Path,
+        fixtures: Option<&Path>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        if let Some(dir) = fixtures {
+            self.load_fixtures(dir, now)?;
+        } else {
+            self.weather.fetch_blocking()?;
+            self.transit.fetch_blocking()?;
+        }
 
-            // Current temperature
-            let temperature = format!("{}\n", now.temperature());
-            let temperature_text = text(
-                &temperature,
-                (Display::LEFT, Display::TOP),
-                FontSize::Large,
-                Alignment::Left,
-            );
-            let temperature_right =
-                temperature_text.bounding_box().anchor_x(AnchorX::Right);
-            let mut next = self.display.draw_text(&temperature_text);
-            next.y += 8; // Padding
-
-            // Draw current PoP just to the right
-            self.display.draw_text(&text(
-                &now.prob_of_precip(),
+        self.draw(now);
+        self.display.draw()?;
+        self.display.frame().write_png(path)?;
+        info!("Wrote frame to {path:?}");
+        Ok(())
+    }
+
+    /// Load recorded API responses from a fixture directory, in place of
+    /// live data, as if they were fetched at the given time
+    fn load_fixtures(
+        &self,
+        dir: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.weather.load_fixtures(dir, fetched_at)?;
+        self.transit.load_fixtures(dir, fetched_at)?;
+        Ok(())
+    }
+
+    /// Draw screen contents to the buffer (but don't update the hardware).
+    /// Returns the forecast and predictions that were drawn
+    fn draw(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> (Option<(Forecast, DateTime<Utc>)>, Predictions) {
+        let forecast = self.weather.forecast();
+        let predictions = self.transit.predictions(now);
+        let weather_right = self.draw_weather(now, forecast.as_ref());
+        self.draw_transit(now, &predictions, weather_right);
+        (forecast, predictions)
+    }
+
+    /// Draw the weather column. Returns the x coordinate where the weather
+    /// ends beside the current temperature, so the transit column knows how
+    /// much room its first row has
+    fn draw_weather(
+        &mut self,
+        now: DateTime<Utc>,
+        forecast: Option<&(Forecast, DateTime<Utc>)>,
+    ) -> i32 {
+        // The observation and alerts are fetched separately from the
+        // forecast, so they're still worth showing when the forecast is too
+        // old to trust
+        let forecast = forecast.and_then(|(forecast, fetched_at)| {
+            let freshness = self.weather_staleness.check(*fetched_at, now);
+            (freshness != Freshness::Expired).then_some((
+                forecast,
+                *fetched_at,
+                freshness,
+            ))
+        });
+        let current =
+            forecast.and_then(|(forecast, _, _)| forecast.current(now));
+        // Current temperature. The forecast for this hour is only a guess, so
+        // prefer what the station is actually seeing
+        let observed = self
+            .weather
+            .observation(now)
+            .and_then(|observation| observation.temperature());
+        let temperature = observed
+            .or_else(|| current.as_ref().map(|current| current.temperature()));
+        let alert = self.weather.alert(now);
+        // Late in the day, today's summary is just tonight's low, so move on
+        // to tomorrow
+        let daily = forecast.and_then(|_| {
+            self.weather
+                .daily(now)
+                .into_iter()
+                .find(|day| day.high.is_some())
+        });
+        let Some(temperature) = temperature else {
+            return Display::LEFT;
+        };
+        let temperature = format!("{temperature}\n");
+        let temperature_text = text(
+            &temperature,
+            (Display::LEFT, Display::TOP),
+            FontSize::Large,
+            Alignment::Left,
+        );
+        let temperature_right =
+            temperature_text.bounding_box().anchor_x(AnchorX::Right);
+        let mut next = self.display.draw_text(&temperature_text);
+        next.y += 8; // Padding
+
+        // Draw current PoP just to the right
+        let mut weather_right = temperature_right;
+        if let Some(current) = &current {
+            let prob_of_precip = current.prob_of_precip();
+            let prob_of_precip = text(
+                &prob_of_precip,
                 (temperature_right, Display::TOP),
                 FontSize::Medium,
                 Alignment::Left,
+            );
+            weather_right =
+                prob_of_precip.bounding_box().anchor_x(AnchorX::Right);
+            self.display.draw_text(&prob_of_precip);
+        }
+        if self.weather.is_refreshing() {
+            self.display.draw_indicator(Point::new(
+                temperature_right + 6,
+                Display::TOP + 28,
             ));
-
-            // Show the next n periods
-            for period in forecast.future_periods().take(Self::WEATHER_PERIODS)
-            {
-                next = self.display.draw_text(&text(
-                    &format!(
-                        "{} {:>4} {:>4}\n",
-                        period.start_time().format("%_I%P"),
-                        period.temperature(),
-                        period.prob_of_precip(),
-                    ),
-                    next,
-                    FontSize::Medium,
-                    Alignment::Left,
-                ));
+        }
+        // Flag old data just right of the refresh indicator. With a wide
+        // temperature there may only be room for the time, or nothing at
+        // all, in which case it gets a row of its own below
+        let mut stale_row = None;
+        if let Some((_, fetched_at, Freshness::Stale)) = forecast {
+            let candidates = [
+                as_of(fetched_at, self.timezone),
+                fetched_time(fetched_at, self.timezone),
+            ];
+            let badge = candidates
+                .iter()
+                .map(|candidate| {
+                    text(
+                        candidate,
+                        (temperature_right + 14, Display::TOP + 22),
+                        FontSize::Small,
+                        Alignment::Left,
+                    )
+                })
+                .find(|badge| {
+                    badge.bounding_box().anchor_x(AnchorX::Right)
+                        + Display::BADGE_PADDING
+                        <= Self::TRANSIT_LEFT
+                });
+            match badge {
+                Some(badge) => self.display.draw_badge(&badge),
+                None => stale_row = Some(as_of(fetched_at, self.timezone)),
+            }
+        }
+        // Daily summary goes under all that, in whatever room is left
+        // before the transit column. It always says which day it is, since
+        // it switches to tomorrow in the evening
+        if let Some(day) = daily {
+            let high_low = day.high_low();
+            let candidates = [
+                day.to_string(),
+                format!("{} {high_low}", day.label),
+                format!(
+                    "{} {high_low} {}",
+                    day.short_label, day.short_forecast
+                ),
+                format!("{} {high_low}", day.short_label),
+            ];
+            let summary = candidates
+                .iter()
+                .map(|candidate| {
+                    text(
+                        candidate,
+                        (temperature_right, Display::TOP + 36),
+                        FontSize::Small,
+                        Alignment::Left,
+                    )
+                })
+                .find(|summary| {
+                    summary.bounding_box().anchor_x(AnchorX::Right)
+                        <= Self::TRANSIT_LEFT
+                });
+            if let Some(summary) = summary {
+                self.display.draw_text(&summary);
             }
         }
 
-        // Transit
-        let predictions = self.transit.predictions();
-        let mut next = Point::new(Display::RIGHT, Display::TOP);
-        for line in predictions.lines {
+        // A watch or warning takes over the top of the period list, followed
+        // by the staleness badge if it needs its own row. Together they push
+        // out however many hourly rows they cover
+        let banners_top = next.y;
+        for banner in alert.iter().chain(&stale_row) {
+            let badge = text(
+                banner,
+                (Display::LEFT + Display::BADGE_PADDING, next.y),
+                FontSize::Small,
+                Alignment::Left,
+            );
+            self.display.draw_badge(&badge);
+            next.y += badge.bounding_box().size.height as i32 + 4;
+        }
+        let height = (next.y - banners_top) as u32;
+        let periods = Self::WEATHER_PERIODS.saturating_sub(
+            height.div_ceil(FontSize::Medium.line_height()) as usize,
+        );
+
+        // Show the next n periods
+        let periods = forecast.into_iter().flat_map(|(forecast, _, _)| {
+            forecast.future_periods(now, self.timezone).take(periods)
+        });
+        for period in periods {
             next = self.display.draw_text(&text(
                 &format!(
-                    "{}\n{}\n{}\n",
-                    line.name, line.inbound, line.outbound
+                    "{} {:>4} {:>4}\n",
+                    period.start_time(self.timezone).format("%_I%P"),
+                    period.temperature(),
+                    period.prob_of_precip(),
                 ),
                 next,
                 FontSize::Medium,
+                Alignment::Left,
+            ));
+        }
+        weather_right
+    }
+
+    /// Draw the transit column. `weather_right` is where the weather ends
+    /// beside the current temperature
+    fn draw_transit(
+        &mut self,
+        now: DateTime<Utc>,
+        predictions: &Predictions,
+        weather_right: i32,
+    ) {
+        let freshness = predictions
+            .fetched_at
+            .map(|fetched_at| self.transit_staleness.check(fetched_at, now));
+        if freshness == Some(Freshness::Expired) {
+            return;
+        }
+
+        // Old predictions are always flagged, since the cues computed from
+        // them can't be trusted without knowing how old they are. The badge
+        // is shortened to just the time if that's all that fits
+        let stale_badges = predictions
+            .fetched_at
+            .filter(|_| freshness == Some(Freshness::Stale))
+            .map(|fetched_at| {
+                [
+                    as_of(fetched_at, self.timezone),
+                    fetched_time(fetched_at, self.timezone),
+                ]
+            });
+        let mut next = Point::new(Display::RIGHT, Display::TOP);
+        for (i, line) in predictions.lines.iter().enumerate() {
+            // Everything else on the name row is stacked leftward from the
+            // name, most important first: (on the first line only) the
+            // refresh indicator and staleness badge, then alert and leave
+            // label. The first line sits beside the current temperature, the
+            // rest beside the hourly rows
+            let limit = if i == 0 {
+                weather_right + 6
+            } else {
+                Self::TRANSIT_LEFT
+            };
+            // If the staleness badge doesn't fit, cut the name short until it
+            // does
+            let mut name = line.name.as_str();
+            let (mut row, indicator, badge) = loop {
+                let mut row = NameRow::new(name, next, limit);
+                if i > 0 {
+                    break (row, None, None);
+                }
+                // Leave room for the indicator even when it's not shown, so
+                // the badge doesn't jump around
+                let indicator = row.indicator();
+                let Some(badges) = &stale_badges else {
+                    break (row, indicator, None);
+                };
+                if let Some(badge) =
+                    badges.iter().find_map(|badge| row.label(badge))
+                {
+                    break (row, indicator, Some(badge));
+                }
+                match name.char_indices().last() {
+                    Some((end, _)) => name = &name[..end],
+                    None => break (row, indicator, None),
+                }
+            };
+            if let Some(center) = indicator
+                && self.transit.is_refreshing()
+            {
+                self.display.draw_indicator(center);
+            }
+            if let Some(badge) = badge {
+                self.display.draw_badge(&badge);
+            }
+            if let Some(alert) = &line.alert
+                && let Some(badge) = row.label(alert)
+            {
+                self.display.draw_badge(&badge);
+            }
+            if let Some(label) = &line.leave_label()
+                && let Some(label) = row.label(label)
+            {
+                self.display.draw_text(&label);
+            }
+
+            let top = next.y;
+            next = self.display.draw_text(&text(
+                &format!("{name}\n{}\n{}\n", line.inbound, line.outbound),
+                next,
+                FontSize::Medium,
                 Alignment::Right,
             ));
+            // Invert the departure we need to leave for right now. The rows
+            // are right-aligned and monospace, so it's the start of the row
+            let line_height = FontSize::Medium.line_height() as i32;
+            for (row, countdowns) in
+                [&line.inbound, &line.outbound].into_iter().enumerate()
+            {
+                if countdowns.leave_now()
+                    && let Some(first) = countdowns.first()
+                {
+                    let y = top + (row as i32 + 1) * line_height;
+                    let left = text(
+                        &countdowns.to_string(),
+                        (Display::RIGHT, y),
+                        FontSize::Medium,
+                        Alignment::Right,
+                    )
+                    .bounding_box()
+                    .top_left
+                    .x;
+                    self.display.draw_badge(&text(
+                        &first,
+                        (left, y),
+                        FontSize::Medium,
+                        Alignment::Left,
+                    ));
+                }
+            }
             // The returned x is a bit shifted for some reason, so reset it
             next.x = Display::RIGHT;
             next.y += 8; // Padding between lines
         }
     }
 }
+
+/// Labels stacked leftward from a transit line's name, up to a limit. A label
+/// that doesn't fit is dropped, so the most important ones should go first
+struct NameRow {
+    /// Left edge of the last thing on the row
+    left: i32,
+    /// Nothing may be drawn left of this
+    limit: i32,
+    /// Top of the row
+    y: i32,
+}
+
+impl NameRow {
+    /// Gap between items on the row
+    const GAP: i32 = 6;
+
+    /// Start a row left of a line's name, which is right-aligned at `at`
+    fn new(name: &str, at: Point, limit: i32) -> Self {
+        let name = text(name, at, FontSize::Medium, Alignment::Right);
+        Self {
+            left: name.bounding_box().top_left.x,
+            limit,
+            y: at.y,
+        }
+    }
+
+    /// Place a small label on the row. `None` if it doesn't fit
+    fn label<'a>(&mut self, label: &'a str) -> Option<Text<'a>> {
+        let label = text(
+            label,
+            (self.left - Self::GAP, self.y + 4),
+            FontSize::Small,
+            Alignment::Right,
+        );
+        // Leave room for the padding, in case it's drawn as a badge
+        let left = label.bounding_box().top_left.x - Display::BADGE_PADDING;
+        if left < self.limit {
+            debug!("No room on name row for {:?}", label.text);
+            return None;
+        }
+        self.left = left;
+        Some(label)
+    }
+
+    /// Place the refresh indicator on the row, returning its center. `None`
+    /// if it doesn't fit
+    fn indicator(&mut self) -> Option<Point> {
+        let center = Point::new(self.left - Self::GAP, self.y + 9);
+        let left = center.x - 2;
+        if left < self.limit {
+            return None;
+        }
+        self.left = left;
+        Some(center)
+    }
+}
+
+/// Label for the time some old data was fetched
+fn as_of(fetched_at: DateTime<Utc>, timezone: LocalTz) -> String {
+    format!("as of {}", fetched_time(fetched_at, timezone))
+}
+
+/// Local time some old data was fetched, for when there's no room to say
+/// more
+fn fetched_time(fetched_at: DateTime<Utc>, timezone: LocalTz) -> String {
+    timezone.localize(fetched_at).format("%H:%M").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::test_config, display::Frame, http::StubTransport,
+        util::fixture_now,
+    };
+    use std::{env, fs};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    const SNAPSHOTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/snapshots");
+    /// Where to write actual/diff images for failed snapshots
+    const FAILURES: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/target/snapshots");
+    /// Set this env var to overwrite reference images with the current output
+    const UPDATE_VAR: &str = "GOLDFINGER_UPDATE_SNAPSHOTS";
+
+    /// Build a controller with the in-memory backend and the fixture config
+    fn controller() -> Controller {
+        controller_with(|_| {})
+    }
+
+    /// Build a controller with some changes to the fixture config
+    fn controller_with(modify: impl FnOnce(&mut Config)) -> Controller {
+        let mut config = test_config();
+        modify(&mut config);
+        assert!(matches!(config.display_backend, BackendKind::Memory));
+        // Any data a test needs should come from fixtures
+        Controller::new(&config, Arc::new(StubTransport::default())).unwrap()
+    }
+
+    /// Draw a frame and compare it pixel by pixel to the reference image
+    fn assert_snapshot(
+        name: &str,
+        controller: &mut Controller,
+        now: DateTime<Utc>,
+    ) {
+        controller.draw(now);
+        controller.display.draw().unwrap();
+        let frame = controller.display.frame();
+
+        let reference_path = Path::new(SNAPSHOTS).join(format!("{name}.png"));
+        if env::var_os(UPDATE_VAR).is_some() {
+            fs::create_dir_all(SNAPSHOTS).unwrap();
+            frame.write_png(&reference_path).unwrap();
+            return;
+        }
+
+        fs::create_dir_all(FAILURES).unwrap();
+        let actual_path = Path::new(FAILURES).join(format!("{name}.png"));
+        let Ok(reference) = fs::read(&reference_path) else {
+            frame.write_png(&actual_path).unwrap();
+            panic!(
+                "Missing reference image {reference_path:?}. Output written \
+                to {actual_path:?}; re-run with {UPDATE_VAR}=1 to accept it"
+            );
+        };
+        let expected = Frame::from_png(&reference).unwrap();
+        if let Some((mismatches, diff)) = frame.diff(&expected).unwrap() {
+            let diff_path =
+                Path::new(FAILURES).join(format!("{name}.diff.png"));
+            frame.write_png(&actual_path).unwrap();
+            fs::write(&diff_path, diff).unwrap();
+            panic!(
+                "Snapshot {name} differs by {mismatches} pixels. Output \
+                written to {actual_path:?}, diff to {diff_path:?}; re-run \
+                with {UPDATE_VAR}=1 to accept it"
+            );
+        }
+    }
+
+    /// Full screen with weather and transit data
+    #[test]
+    fn test_snapshot_full() {
+        let mut controller = controller();
+        controller
+            .load_fixtures(Path::new(FIXTURES), fixture_now())
+            .unwrap();
+        assert_snapshot("full", &mut controller, fixture_now());
+    }
+
+    /// With a walk time, we say when to leave and highlight the departure
+    /// to leave for now
+    #[test]
+    fn test_snapshot_walk() {
+        let mut controller = controller_with(|config| {
+            config.transit_lines[0].walk_minutes = 4;
+        });
+        controller
+            .load_fixtures(Path::new(FIXTURES), fixture_now())
+            .unwrap();
+        assert_snapshot("walk", &mut controller, fixture_now());
+    }
+
+    /// With only one departure left, the highlight stops at its unit
+    #[test]
+    fn test_snapshot_walk_single() {
+        let mut controller = controller_with(|config| {
+            config.transit_lines[0].walk_minutes = 4;
+        });
+        // Only the last inbound 87 is still catchable
+        let now = fixture_now() + chrono::Duration::minutes(32);
+        controller.load_fixtures(Path::new(FIXTURES), now).unwrap();
+        assert_snapshot("walk_single", &mut controller, now);
+    }
+
+    /// Old data gets an "as of" badge
+    #[test]
+    fn test_snapshot_stale() {
+        let mut controller = controller();
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::minutes(10),
+            )
+            .unwrap();
+        // Transit predictions are stale after 5 minutes, weather after 30.
+        // Countdowns are relative to the same `now`, so they're unchanged
+        assert_snapshot("stale", &mut controller, fixture_now());
+    }
+
+    /// The staleness badge always makes it onto the first line's name row.
+    /// The alert that no longer fits beside the current temperature is
+    /// dropped instead
+    #[test]
+    fn test_snapshot_alert_stale() {
+        // Put the line with the alert first
+        let mut controller = controller_with(|config| {
+            config.transit_lines.reverse();
+        });
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::minutes(10),
+            )
+            .unwrap();
+        assert_snapshot("alert_stale", &mut controller, fixture_now());
+    }
+
+    /// With a walk time, an alert, and stale data all on the first line, the
+    /// staleness badge is kept, since a leave cue from old predictions can't
+    /// be trusted without it. The leave label gets whatever room is left
+    #[test]
+    fn test_snapshot_walk_alert_stale() {
+        let mut controller = controller_with(|config| {
+            config.transit_lines.reverse();
+            config.transit_lines[0].walk_minutes = 4;
+        });
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::minutes(10),
+            )
+            .unwrap();
+        assert_snapshot("walk_alert_stale", &mut controller, fixture_now());
+    }
+
+    /// When a long name leaves no room for the staleness badge on the first
+    /// line, the name is cut short so the badge still fits. Cues computed
+    /// from old data are only trustworthy with it there
+    #[test]
+    fn test_snapshot_long_name_stale() {
+        let mut controller = controller_with(|config| {
+            config.transit_lines.reverse();
+            config.transit_lines[0].route = Some("88".into());
+            config.transit_lines[0].name = "Harvard".into();
+            config.transit_lines[0].walk_minutes = 4;
+            config.transit_lines[1].walk_minutes = 4;
+        });
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::minutes(10),
+            )
+            .unwrap();
+        assert_snapshot("long_name_stale", &mut controller, fixture_now());
+    }
+
+    /// A wide temperature leaves no room for the staleness badge beside it,
+    /// so it gets its own row above the hourly forecast
+    #[test]
+    fn test_snapshot_wide_temperature_stale() {
+        // Copy the fixtures, with a heat wave
+        let dir = env::temp_dir()
+            .join(format!("goldfinger-test-fixtures-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for entry in fs::read_dir(FIXTURES).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+        let path = dir.join("observation.json");
+        let mut observation: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        observation["properties"]["temperature"]["value"] = 37.8.into();
+        fs::write(&path, observation.to_string()).unwrap();
+
+        let mut controller = controller();
+        controller
+            .load_fixtures(&dir, fixture_now() - chrono::Duration::minutes(40))
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_snapshot(
+            "wide_temperature_stale",
+            &mut controller,
+            fixture_now(),
+        );
+    }
+
+    /// Data past its expiry isn't shown at all
+    #[test]
+    fn test_snapshot_expired() {
+        let mut controller = controller();
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::hours(1),
+            )
+            .unwrap();
+        assert_snapshot("expired", &mut controller, fixture_now());
+    }
+
+    /// An expired forecast doesn't take the current observation and weather
+    /// alert down with it, since those are still fresh
+    #[test]
+    fn test_snapshot_forecast_expired() {
+        let mut controller = controller();
+        controller
+            .load_fixtures(
+                Path::new(FIXTURES),
+                fixture_now() - chrono::Duration::hours(7),
+            )
+            .unwrap();
+        assert_snapshot("forecast_expired", &mut controller, fixture_now());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    display::Frame,
+    transit::Predictions,
+    util::FetchStatus,
+    weather::{Forecast, ForecastPeriod},
+};
+use anyhow::anyhow;
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    thread,
+};
+use tiny_http::{Header, Request, Response, Server};
+
+/// Embedded HTTP server to preview the current frame, and the data that went
+/// into it, from a browser
+pub struct PreviewServer {
+    /// `None` until the first tick
+    state: Arc<RwLock<Option<PreviewState>>>,
+}
+
+/// Everything the server can show. Updated by the controller on every tick
+pub struct PreviewState {
+    pub frame: Frame,
+    pub forecast: Option<Forecast>,
+    pub predictions: Predictions,
+    pub status: Status,
+}
+
+/// Status of each data source
+#[derive(Serialize)]
+pub struct Status {
+    /// Keyed by endpoint
+    pub weather: BTreeMap<&'static str, FetchStatus>,
+    /// Keyed by the transit provider's name for each source
+    pub transit: BTreeMap<&'static str, FetchStatus>,
+}
+
+impl PreviewServer {
+    /// How often the browser should reload the page, in seconds
+    const REFRESH_INTERVAL: u32 = 5;
+
+    /// Bind to the address and start serving requests on a background thread
+    pub fn start(address: SocketAddr) -> anyhow::Result<Self> {
+        // Stringify the error to make it Sync
+        let server = Server::http(address)
+            .map_err(|err| anyhow!("Error starting preview server: {err}"))?;
+        info!("Preview server listening on http://{address}");
+
+        let state: Arc<RwLock<Option<PreviewState>>> = Default::default();
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(request, &thread_state);
+            }
+        });
+        Ok(Self { state })
+    }
+
+    /// Replace the displayed state with the latest from the controller
+    pub fn update(&self, state: PreviewState) {
+        match self.state.write() {
+            Ok(mut guard) => *guard = Some(state),
+            Err(err) => error!("Error updating preview state: {err}"),
+        }
+    }
+}
+
+/// Respond to a single request
+fn handle(request: Request, state: &RwLock<Option<PreviewState>>) {
+    let response = respond(request.url(), state);
+    let url = request.url().to_owned();
+    if let Err(err) = request.respond(response) {
+        warn!("Error responding to preview request {url}: {err}");
+    }
+}
+
+/// Build the response for a URL
+fn respond(
+    url: &str,
+    state: &RwLock<Option<PreviewState>>,
+) -> Response<Cursor<Vec<u8>>> {
+    // Ignore query params
+    let path = url.split('?').next().unwrap_or_default();
+    match state.read() {
+        Ok(state) => match (path, state.as_ref()) {
+            ("/", state) => html(&page(state)),
+            (
+                "/frame.png" | "/forecast.json" | "/predictions.json"
+                | "/status.json",
+                None,
+            ) => not_ready(),
+            ("/frame.png", Some(state)) => match state.frame.to_png() {
+                Ok(png) => data(png, "image/png"),
+                Err(err) => server_error(&format!("{err:#}")),
+            },
+            ("/forecast.json", Some(state)) => json(&state.forecast),
+            ("/predictions.json", Some(state)) => json(&state.predictions),
+            ("/status.json", Some(state)) => json(&state.status),
+            _ => {
+                data(b"Not found".to_vec(), "text/plain").with_status_code(404)
+            }
+        },
+        Err(err) => server_error(&err.to_string()),
+    }
+}
+
+/// HTML page that shows the frame at 2x scale and reloads periodically
+fn page(state: Option<&PreviewState>) -> String {
+    let conditions = state
+        .and_then(|state| state.forecast.as_ref())
+        .and_then(|forecast| forecast.current(Utc::now()))
+        .map(conditions)
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Goldfinger</title>
+    <meta http-equiv="refresh" content="{refresh}">
+    <style>
+      img {{
+        width: {width}px;
+        border: 1px solid gray;
+        image-rendering: pixelated;
+      }}
+    </style>
+  </head>
+  <body>
+    <img src="/frame.png" alt="Current frame">
+    {conditions}
+    <ul>
+      <li><a href="/forecast.json">Forecast</a></li>
+      <li><a href="/predictions.json">Predictions</a></li>
+      <li><a href="/status.json">Fetch status</a></li>
+    </ul>
+  </body>
+</html>
+"#,
+        refresh = PreviewServer::REFRESH_INTERVAL,
+        width = Frame::WIDTH * 2,
+    )
+}
+
+/// Forecast details for this hour that don't fit on the screen
+fn conditions(period: &ForecastPeriod) -> String {
+    let unknown = || "--".to_owned();
+    format!(
+        r#"<p><img src="{icon}" alt="{alt}"> {short_forecast}. Wind {wind}, humidity {humidity}, dewpoint {dewpoint}</p>"#,
+        icon = escape(period.icon()),
+        // NWS icons are a day or night scene
+        alt = match period.is_daytime() {
+            Some(true) => "Day",
+            Some(false) => "Night",
+            None => "",
+        },
+        short_forecast = escape(period.short_forecast()),
+        wind = period.wind().unwrap_or_else(unknown),
+        humidity = period.humidity().unwrap_or_else(unknown),
+        dewpoint = period.dewpoint().unwrap_or_else(unknown),
+    )
+}
+
+/// Escape text from an API for use in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html(body: &str) -> Response<Cursor<Vec<u8>>> {
+    data(body.as_bytes().to_owned(), "text/html; charset=utf-8")
+}
+
+fn json(value: &impl Serialize) -> Response<Cursor<Vec<u8>>> {
+    match serde_json::to_vec_pretty(value) {
+        Ok(body) => data(body, "application/json"),
+        Err(err) => server_error(&err.to_string()),
+    }
+}
+
+/// Nothing has been drawn yet
+fn not_ready() -> Response<Cursor<Vec<u8>>> {
+    data(b"No frame drawn yet".to_vec(), "text/plain").with_status_code(503)
+}
+
+fn server_error(message: &str) -> Response<Cursor<Vec<u8>>> {
+    data(message.as_bytes().to_owned(), "text/plain").with_status_code(500)
+}
+
+fn data(body: Vec<u8>, content_type: &str) -> Response<Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", content_type)
+        .expect("Content-Type header is valid");
+    Response::from_data(body).with_header(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// Get the status code and body for a URL
+    fn get(url: &str, state: &RwLock<Option<PreviewState>>) -> (u16, String) {
+        let response = respond(url, state);
+        let status = response.status_code().0;
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        (status, body)
+    }
+
+    /// The page is served before anything has been drawn, and links to
+    /// everything else
+    #[test]
+    fn test_page() {
+        let state = RwLock::new(None);
+        let (status, body) = get("/?from=bookmark", &state);
+        assert_eq!(status, 200);
+        for link in [
+            "/frame.png",
+            "/forecast.json",
+            "/predictions.json",
+            "/status.json",
+        ] {
+            assert!(body.contains(&format!("\"{link}\"")), "{body}");
+        }
+        // No forecast, so no conditions
+        assert!(!body.contains("<p>"), "{body}");
+    }
+
+    /// Data endpoints are unavailable until the first tick
+    #[test]
+    fn test_not_ready() {
+        let state = RwLock::new(None);
+        for url in [
+            "/frame.png",
+            "/forecast.json",
+            "/predictions.json",
+            "/status.json",
+        ] {
+            assert_eq!(get(url, &state), (503, "No frame drawn yet".into()));
+        }
+        assert_eq!(get("/nope", &state), (404, "Not found".into()));
+    }
+}
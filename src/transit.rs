@@ -1,187 +1,649 @@
-use crate::{config::Config, util::ApiFetcher};
+mod gtfs_realtime;
+mod gtfs_static;
+mod mbta;
+
+use self::{
+    gtfs_realtime::{GtfsRealtime, GtfsRealtimeConfig},
+    gtfs_static::{GtfsStatic, GtfsStaticConfig},
+    mbta::Mbta,
+};
+use crate::{
+    config::Config,
+    http::Transport,
+    util::{FetchStatus, RetryPolicy},
+};
 use chrono::{DateTime, Utc};
-use indexmap::IndexMap;
 use itertools::Itertools;
-use log::error;
-use serde::Deserialize;
-use std::{fmt::Display, time::Duration};
+use log::trace;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 #[derive(Debug)]
 pub struct Transit {
-    fetcher: ApiFetcher<ApiPredictions>,
+    /// Where departures come from
+    provider: Box<dyn TransitProvider>,
     lines: Vec<TransitLine>,
 }
 
 impl Transit {
-    /// Time between fetches
-    const DATA_TTL: Duration = Duration::from_secs(30);
+    /// Back off on failures, but not so much that we miss a bus
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        initial_delay: Duration::from_secs(10),
+        max_delay: Duration::from_secs(5 * 60),
+        multiplier: 2.0,
+        jitter: 0.2,
+    };
     /// Max number of pending departures to show for a stop
     const MAX_PREDICTIONS: usize = 2;
 
-    pub fn new(config: &Config) -> Self {
-        let all_stops = config
-            .transit_lines
-            .iter()
-            .flat_map(|line| [line.inbound_stop, line.outbound_stop]);
-        let url = format!(
-            "https://api-v3.mbta.com/predictions?filter[stop]={}",
-            all_stops.format(",")
-        );
-        Self {
-            fetcher: ApiFetcher::new(url, Self::DATA_TTL),
-            lines: config.transit_lines.clone(),
-        }
+    pub fn new(
+        config: &Config,
+        transport: Arc<dyn Transport>,
+    ) -> anyhow::Result<Self> {
+        let lines = &config.transit_lines;
+        let provider: Box<dyn TransitProvider> = match &config.transit_provider
+        {
+            ProviderConfig::Mbta => Box::new(Mbta::new(config, transport)),
+            ProviderConfig::GtfsRealtime(gtfs_config) => {
+                Box::new(GtfsRealtime::new(
+                    gtfs_config,
+                    config.cache_dir.as_deref(),
+                    transport,
+                ))
+            }
+            ProviderConfig::GtfsStatic(gtfs_config) => {
+                Box::new(GtfsStatic::new(gtfs_config, lines)?)
+            }
+        };
+        Ok(Self {
+            provider,
+            lines: lines.clone(),
+        })
     }
 
-    pub fn predictions(&self) -> Predictions {
-        struct Helper {
-            inbound_stop: u32,
-            outbound_stop: u32,
-            inbound: Vec<DateTime<Utc>>,
-            outbound: Vec<DateTime<Utc>>,
-        }
+    /// Are new predictions being fetched right now?
+    pub fn is_refreshing(&self) -> bool {
+        self.provider.is_refreshing()
+    }
+
+    /// Status of each of the provider's data sources, for debugging
+    pub fn status(&self) -> BTreeMap<&'static str, FetchStatus> {
+        self.provider.status()
+    }
+
+    /// Fetch all data immediately, blocking until it's loaded
+    pub fn fetch_blocking(&self) -> anyhow::Result<()> {
+        self.provider.fetch_blocking()
+    }
+
+    /// Load recorded API responses from a fixture directory instead of the
+    /// API
+    pub fn load_fixtures(
+        &self,
+        dir: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.provider.load_fixtures(dir, fetched_at)
+    }
+
+    /// Get the latest predictions, with countdowns relative to `now`. If a
+    /// stop has no predictions, show its scheduled departures instead
+    pub fn predictions(&self, now: DateTime<Utc>) -> Predictions {
+        let predictions = self.provider.predictions();
+        let fetched_at =
+            predictions.as_ref().map(|(_, fetched_at)| *fetched_at);
+        let schedules = self.provider.schedules(now);
 
-        // Group predictions as (line, (inbound, outbound))
-        let mut grouped: IndexMap<String, Helper> = self
+        // We want to show empty data if we don't have an API response yet
+        let lines = self
             .lines
             .iter()
             .map(|line| {
-                (
-                    line.name.clone(),
-                    Helper {
-                        inbound_stop: line.inbound_stop,
-                        outbound_stop: line.outbound_stop,
-                        inbound: Vec::new(),
-                        outbound: Vec::new(),
-                    },
-                )
+                let (inbound, outbound) = predictions
+                    .as_ref()
+                    .map(|(departures, _)| line.departures(departures))
+                    .unwrap_or_default();
+                let (scheduled_inbound, scheduled_outbound) =
+                    line.departures(&schedules);
+                LinePrediction {
+                    name: line.name.clone(),
+                    alert: self.provider.alert(line, now),
+                    inbound: CountdownList::new(
+                        inbound,
+                        scheduled_inbound,
+                        now,
+                        line.walk_time(),
+                    ),
+                    outbound: CountdownList::new(
+                        outbound,
+                        scheduled_outbound,
+                        now,
+                        line.walk_time(),
+                    ),
+                }
             })
             .collect();
+        Predictions { lines, fetched_at }
+    }
+}
 
-        // Pull data from the most recent response
-        if let Some(data) = self.fetcher.data() {
-            for prediction in data.data {
-                // Departure time will be empty if the stop is being skipped
-                let Some(departure_time) = prediction.attributes.departure_time
-                else {
-                    continue;
-                };
-                let route_id = prediction.relationships.route.data.id;
-                let Some(group) = grouped.get_mut(&route_id) else {
-                    error!("Unknown route {route_id}");
-                    continue;
-                };
-                let stop_id = &prediction.relationships.stop.data.id;
-
-                if stop_id == &group.inbound_stop.to_string() {
-                    group.inbound.push(departure_time);
-                } else if stop_id == &group.outbound_stop.to_string() {
-                    group.outbound.push(departure_time);
-                } else {
-                    error!("Unknown stop {stop_id} for route {route_id}");
-                }
-            }
-        }
+/// A source of departures, e.g. one agency's API. Providers just report
+/// everything they know about the configured stops; matching departures to
+/// lines and building countdowns is shared
+trait TransitProvider: Debug {
+    /// Latest predicted departures, and when they were fetched. `None` if we
+    /// don't have any realtime data (yet). This may kick off a refetch in the
+    /// background
+    fn predictions(&self) -> Option<(Vec<Departure>, DateTime<Utc>)>;
 
-        // We want to show empty data if we don't have an API response yet
-        let lines = grouped
-            .into_iter()
-            .map(|(name, data)| LinePrediction {
-                name,
-                inbound: data.inbound.into(),
-                outbound: data.outbound.into(),
-            })
-            .collect();
-        Predictions { lines }
+    /// Scheduled departures around `now`, to show when a stop has no
+    /// predictions
+    fn schedules(&self, _now: DateTime<Utc>) -> Vec<Departure> {
+        Vec::new()
+    }
+
+    /// Short label for the most severe active alert on a line, e.g. `DETOUR`
+    fn alert(
+        &self,
+        _line: &TransitLine,
+        _now: DateTime<Utc>,
+    ) -> Option<String> {
+        None
     }
+
+    /// Are new predictions being fetched right now?
+    fn is_refreshing(&self) -> bool;
+
+    /// Status of each data source, by name
+    fn status(&self) -> BTreeMap<&'static str, FetchStatus>;
+
+    /// Fetch all data immediately, blocking until it's loaded
+    fn fetch_blocking(&self) -> anyhow::Result<()>;
+
+    /// Load recorded responses from a fixture directory
+    fn load_fixtures(
+        &self,
+        dir: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+}
+
+/// Where to get transit data from
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    /// MBTA v3 API
+    #[default]
+    Mbta,
+    /// Any agency that publishes a GTFS-Realtime TripUpdates feed
+    GtfsRealtime(GtfsRealtimeConfig),
+    /// Schedules only, from a GTFS feed on disk. Works offline
+    GtfsStatic(GtfsStaticConfig),
 }
 
 /// Configuration for a transit line to show predictions for
 #[derive(Clone, Debug, Deserialize)]
 pub struct TransitLine {
     pub name: String,
-    /// ID of the inbound stop you care about
-    pub inbound_stop: u32,
+    /// Route ID, as the provider knows it. Defaults to the name
+    #[serde(default)]
+    pub route: Option<String>,
+    /// ID of the inbound stop you care about. With the MBTA, this can be a
+    /// parent station (e.g. `place-harsq`), to match predictions from any of
+    /// its platforms
+    #[serde(deserialize_with = "stop_id")]
+    pub inbound_stop: String,
     /// ID of the outbound stop you care about
-    pub outbound_stop: u32,
+    #[serde(deserialize_with = "stop_id")]
+    pub outbound_stop: String,
+    /// Only show inbound departures with this `direction_id` (0 or 1).
+    /// Needed when one stop serves both directions
+    #[serde(default)]
+    pub inbound_direction_id: Option<u8>,
+    /// Only show outbound departures with this `direction_id` (0 or 1)
+    #[serde(default)]
+    pub outbound_direction_id: Option<u8>,
+    /// Only show inbound departures whose headsign contains this, e.g. to
+    /// pick one branch of a route
+    #[serde(default)]
+    pub inbound_headsign: Option<String>,
+    /// Only show outbound departures whose headsign contains this
+    #[serde(default)]
+    pub outbound_headsign: Option<String>,
+    /// Minutes it takes to walk to the stops. Departures that leave sooner
+    /// than this are hidden, and we show when to leave for the next one
+    #[serde(default)]
+    pub walk_minutes: u32,
 }
 
-#[derive(Debug)]
+impl TransitLine {
+    fn route(&self) -> &str {
+        self.route.as_deref().unwrap_or(&self.name)
+    }
+
+    fn walk_time(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.walk_minutes.into())
+    }
+
+    fn inbound(&self) -> DirectionFilter<'_> {
+        DirectionFilter {
+            stop: &self.inbound_stop,
+            direction_id: self.inbound_direction_id,
+            headsign: self.inbound_headsign.as_deref(),
+        }
+    }
+
+    fn outbound(&self) -> DirectionFilter<'_> {
+        DirectionFilter {
+            stop: &self.outbound_stop,
+            direction_id: self.outbound_direction_id,
+            headsign: self.outbound_headsign.as_deref(),
+        }
+    }
+
+    /// Get the departures for this line, as (inbound, outbound)
+    fn departures(
+        &self,
+        departures: &[Departure],
+    ) -> (Vec<Departure>, Vec<Departure>) {
+        let inbound = self.inbound();
+        let outbound = self.outbound();
+        let mut split = (Vec::new(), Vec::new());
+        for departure in departures {
+            if departure.route != self.route() {
+                continue;
+            }
+            if inbound.matches(departure) {
+                split.0.push(departure.clone());
+            } else if outbound.matches(departure) {
+                split.1.push(departure.clone());
+            } else {
+                trace!(
+                    "Departure at {} for route {} doesn't match {}",
+                    departure.stop,
+                    self.route(),
+                    self.name
+                );
+            }
+        }
+        split
+    }
+}
+
+/// Criteria for one direction of a [TransitLine]
+struct DirectionFilter<'a> {
+    stop: &'a str,
+    direction_id: Option<u8>,
+    headsign: Option<&'a str>,
+}
+
+impl DirectionFilter<'_> {
+    /// A stop matches if it's the configured stop itself, or one of its child
+    /// platforms
+    fn matches(&self, departure: &Departure) -> bool {
+        (departure.stop == self.stop
+            || departure.parent_station.as_deref() == Some(self.stop))
+            && self.direction_id.is_none_or(|direction_id| {
+                departure.direction_id == Some(direction_id)
+            })
+            && self.headsign.is_none_or(|filter| {
+                departure
+                    .headsign
+                    .as_ref()
+                    .is_some_and(|headsign| headsign.contains(filter))
+            })
+    }
+}
+
+/// Stop IDs are strings, but bus stops are all numeric so accept numbers too
+fn stop_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StopId {
+        String(String),
+        Number(u32),
+    }
+
+    Ok(match StopId::deserialize(deserializer)? {
+        StopId::String(id) => id,
+        StopId::Number(id) => id.to_string(),
+    })
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Predictions {
     pub lines: Vec<LinePrediction>,
+    /// When the underlying data was fetched. `None` if we have none yet
+    pub fetched_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct LinePrediction {
     pub name: String,
+    /// Short label for the most severe active alert on the line, e.g.
+    /// `DETOUR`
+    pub alert: Option<String>,
     pub inbound: CountdownList,
     pub outbound: CountdownList,
 }
 
-#[derive(Debug)]
-pub struct CountdownList(Vec<Countdown>);
-
-/// Convert a list of timestamps into relative offsets from now, sorting and
-/// truncating as necessary
-impl From<Vec<DateTime<Utc>>> for CountdownList {
-    fn from(value: Vec<DateTime<Utc>>) -> Self {
-        let now = Utc::now();
-        let countdowns = value
+impl LinePrediction {
+    /// When to leave to catch the next departure in each direction, e.g.
+    /// `go Now/7m` or `go 3m/-`. The unit follows the last number, like in a
+    /// countdown list. `None` if the line has no walk time
+    pub fn leave_label(&self) -> Option<String> {
+        let leave_in = [&self.inbound.leave_in, &self.outbound.leave_in];
+        if leave_in.iter().all(|leave_in| leave_in.is_none()) {
+            return None;
+        }
+        let last_minutes = leave_in.iter().rposition(|leave_in| {
+            matches!(leave_in, Some(Countdown::Minutes(_)))
+        });
+        let label = leave_in
+            .iter()
+            .enumerate()
+            .map(|(i, leave_in)| match leave_in {
+                Some(countdown) if Some(i) == last_minutes => {
+                    format!("{countdown}m")
+                }
+                Some(countdown) => countdown.to_string(),
+                None => "-".to_owned(),
+            })
+            .join("/");
+        Some(format!("go {label}"))
+    }
+}
+
+/// A single departure from a stop, predicted or scheduled. Times are kept
+/// absolute so countdowns can be computed fresh on every tick
+#[derive(Clone, Debug)]
+struct Departure {
+    route: String,
+    stop: String,
+    /// Station the stop belongs to, if it has one and the provider knows it
+    parent_station: Option<String>,
+    direction_id: Option<u8>,
+    headsign: Option<String>,
+    /// `None` at the first stop of a trip
+    arrival_time: Option<DateTime<Utc>>,
+    departure_time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CountdownList {
+    countdowns: Vec<Countdown>,
+    /// Are these from the schedule, rather than realtime predictions?
+    scheduled: bool,
+    /// How long until we have to leave to catch the first departure. Only
+    /// set if the line has a walk time
+    leave_in: Option<Countdown>,
+}
+
+impl CountdownList {
+    /// Convert a list of departures into countdowns relative to now, dropping
+    /// any that we can't make it to in time, then sorting and truncating as
+    /// necessary. If there are no predicted departures still to come, fall
+    /// back to scheduled ones. Predictions that we just can't walk to in time
+    /// still count, so they aren't swapped out for schedule guesses
+    fn new(
+        predicted: Vec<Departure>,
+        scheduled: Vec<Departure>,
+        now: DateTime<Utc>,
+        walk_time: chrono::Duration,
+    ) -> Self {
+        // Anything that leaves before we can get to the stop is a lost cause
+        let earliest = now + walk_time;
+        let is_scheduled = !predicted
+            .iter()
+            .any(|departure| departure.departure_time >= now);
+        let mut departures = Self::upcoming(predicted, earliest);
+        if is_scheduled {
+            // A scheduled arrival doesn't mean the bus is actually at the stop
+            let scheduled = scheduled.into_iter().map(|departure| Departure {
+                arrival_time: None,
+                ..departure
+            });
+            departures = Self::upcoming(scheduled, earliest);
+        }
+
+        let leave_in = departures
+            .first()
+            .filter(|_| walk_time > chrono::Duration::zero())
+            .map(|departure| {
+                Countdown::until(departure.departure_time, earliest)
+            });
+        Self {
+            countdowns: departures
+                .iter()
+                .map(|departure| Countdown::new(departure, now))
+                .collect(),
+            scheduled: is_scheduled && !departures.is_empty(),
+            leave_in,
+        }
+    }
+
+    /// Is it time to leave for the first departure?
+    pub fn leave_now(&self) -> bool {
+        self.leave_in == Some(Countdown::Now)
+    }
+
+    /// The first countdown, as it's shown at the start of the list. That
+    /// includes the unit if it's the only one, but never the schedule marker
+    pub fn first(&self) -> Option<String> {
+        let first = self.countdowns.first()?;
+        Some(match first {
+            Countdown::Minutes(_) if self.countdowns.len() == 1 => {
+                format!("{first}m")
+            }
+            _ => first.to_string(),
+        })
+    }
+
+    /// Get the first n departures leaving no earlier than `earliest`
+    fn upcoming(
+        departures: impl IntoIterator<Item = Departure>,
+        earliest: DateTime<Utc>,
+    ) -> Vec<Departure> {
+        departures
             .into_iter()
-            // Get the first n upcoming timestamps
-            .sorted()
+            .filter(|departure| departure.departure_time >= earliest)
+            .sorted_by_key(|departure| departure.departure_time)
             .take(Transit::MAX_PREDICTIONS)
-            .map(|dt| Countdown((dt - now).num_minutes()))
-            .collect();
-        Self(countdowns)
+            .collect()
     }
 }
 
 impl Display for CountdownList {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}m", self.0.iter().format(","))
+        write!(f, "{}", self.countdowns.iter().format(","))?;
+        // Unit goes at the end, but only if the last one is a number
+        if let Some(Countdown::Minutes(_)) = self.countdowns.last() {
+            write!(f, "m")?;
+        }
+        // Mark schedules so they aren't mistaken for live data
+        if self.scheduled {
+            write!(f, "*")?;
+        }
+        Ok(())
     }
 }
 
-/// Number of minutes until an event
-#[derive(Debug)]
-pub struct Countdown(i64);
+/// Time until a departure, the way MBTA signage shows it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Countdown {
+    /// Vehicle is at the stop and hasn't left yet
+    Boarding,
+    /// Leaving in less than a minute
+    Now,
+    Minutes(i64),
+}
+
+impl Countdown {
+    fn new(departure: &Departure, now: DateTime<Utc>) -> Self {
+        if departure
+            .arrival_time
+            .is_some_and(|arrival_time| arrival_time <= now)
+        {
+            Self::Boarding
+        } else {
+            Self::until(departure.departure_time, now)
+        }
+    }
+
+    /// Time until some point, regardless of where the vehicle is
+    fn until(time: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        let minutes = (time - now).num_minutes();
+        if minutes < 1 {
+            Self::Now
+        } else {
+            Self::Minutes(minutes)
+        }
+    }
+}
 
 impl Display for Countdown {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Self::Boarding => write!(f, "Brd"),
+            Self::Now => write!(f, "Now"),
+            Self::Minutes(minutes) => write!(f, "{minutes}"),
+        }
     }
 }
 
-/// <https://api-v3.mbta.com/docs/swagger/index.html#/Prediction/ApiWeb_PredictionController_index>
-#[derive(Clone, Debug, Deserialize)]
-struct ApiPredictions {
-    data: Vec<Prediction>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture_now;
 
-#[derive(Clone, Debug, Deserialize)]
-struct Prediction {
-    attributes: Attributes,
-    relationships: Relationships,
-}
+    #[test]
+    fn test_countdowns() {
+        let countdowns = |departures| {
+            CountdownList::new(
+                departures,
+                Vec::new(),
+                fixture_now(),
+                chrono::Duration::zero(),
+            )
+        };
 
-#[derive(Clone, Debug, Deserialize)]
-struct Attributes {
-    departure_time: Option<DateTime<Utc>>,
-}
+        // Departed already
+        assert_eq!(countdowns(vec![departure(None, -30)]).to_string(), "");
+        assert_eq!(
+            countdowns(vec![
+                departure(Some(-30), -1),
+                departure(Some(130), 130),
+                departure(Some(30), 30),
+            ])
+            .to_string(),
+            "Now,2m"
+        );
+        assert_eq!(
+            countdowns(vec![
+                departure(Some(300), 300),
+                departure(Some(-10), 20),
+            ])
+            .to_string(),
+            "Brd,5m"
+        );
+        assert_eq!(countdowns(vec![departure(None, 45)]).to_string(), "Now");
+    }
 
-#[derive(Clone, Debug, Deserialize)]
-struct Relationships {
-    route: Relationship,
-    stop: Relationship,
-}
+    /// Departures we can't walk to in time are hidden, and the next one tells
+    /// us when to leave
+    #[test]
+    fn test_walk_time() {
+        let countdowns = |departures, walk_minutes| {
+            CountdownList::new(
+                departures,
+                Vec::new(),
+                fixture_now(),
+                chrono::Duration::minutes(walk_minutes),
+            )
+        };
+        let departures = || {
+            vec![
+                departure(Some(-10), 20),
+                departure(None, 4 * 60),
+                departure(None, 6 * 60 + 30),
+                departure(None, 15 * 60),
+            ]
+        };
 
-#[derive(Clone, Debug, Deserialize)]
-struct Relationship {
-    data: RelationshipData,
-}
+        let list = countdowns(departures(), 5);
+        assert_eq!(list.to_string(), "6,15m");
+        assert_eq!(list.leave_in, Some(Countdown::Minutes(1)));
+        assert!(!list.leave_now());
 
-#[derive(Clone, Debug, Deserialize)]
-struct RelationshipData {
-    id: String,
+        let list = countdowns(departures(), 6);
+        assert_eq!(list.to_string(), "6,15m");
+        assert!(list.leave_now());
+        assert_eq!(list.first().as_deref(), Some("6"));
+
+        // No walk time, no advice
+        let list = countdowns(departures(), 0);
+        assert_eq!(list.to_string(), "Brd,4m");
+        assert_eq!(list.leave_in, None);
+
+        // Predictions we can't make aren't replaced by the schedule
+        let list = CountdownList::new(
+            vec![departure(None, 2 * 60)],
+            vec![departure(None, 10 * 60)],
+            fixture_now(),
+            chrono::Duration::minutes(5),
+        );
+        assert_eq!(list.to_string(), "");
+        assert!(!list.scheduled);
+        // But with nothing predicted, the schedule fills in
+        let list = CountdownList::new(
+            Vec::new(),
+            vec![departure(None, 10 * 60)],
+            fixture_now(),
+            chrono::Duration::minutes(5),
+        );
+        assert_eq!(list.to_string(), "10m*");
+        assert_eq!(list.first().as_deref(), Some("10m"));
+
+        let line = LinePrediction {
+            name: "87".into(),
+            alert: None,
+            inbound: countdowns(departures(), 6),
+            outbound: countdowns(departures(), 5),
+        };
+        assert_eq!(line.leave_label().as_deref(), Some("go Now/1m"));
+
+        // Only one direction has anything we can make
+        let line = LinePrediction {
+            name: "87".into(),
+            alert: None,
+            inbound: countdowns(vec![departure(None, 8 * 60)], 5),
+            outbound: countdowns(Vec::new(), 5),
+        };
+        assert_eq!(line.leave_label().as_deref(), Some("go 3m/-"));
+    }
+
+    /// Departure some seconds from now
+    fn departure(arrival: Option<i64>, departure: i64) -> Departure {
+        Departure {
+            route: "87".into(),
+            stop: "2545".into(),
+            parent_station: None,
+            direction_id: None,
+            headsign: None,
+            arrival_time: arrival.map(|seconds| {
+                fixture_now() + chrono::Duration::seconds(seconds)
+            }),
+            departure_time: fixture_now()
+                + chrono::Duration::seconds(departure),
+        }
+    }
 }
@@ -0,0 +1,562 @@
+//! Scheduled departures from a
+//! [GTFS static](https://gtfs.org/documentation/schedule/reference/) feed zip
+//! on disk, for agencies with no realtime feed or for running offline. The
+//! feed is loaded once on startup, keeping only the configured routes and
+//! stops. Times are in the agency's timezone, wherever goldfinger runs
+
+use super::{Departure, TransitLine, TransitProvider};
+use crate::util::{FetchStatus, LocalTz};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+use log::info;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+use zip::{result::ZipError, ZipArchive};
+
+/// Config for a GTFS static feed
+#[derive(Clone, Debug, Deserialize)]
+pub struct GtfsStaticConfig {
+    /// Path to the feed zip
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct GtfsStatic {
+    schedule: Schedule,
+    /// When the feed was loaded
+    loaded_at: DateTime<Utc>,
+}
+
+impl GtfsStatic {
+    pub fn new(
+        config: &GtfsStaticConfig,
+        lines: &[TransitLine],
+    ) -> anyhow::Result<Self> {
+        let schedule =
+            Schedule::load(&config.path, lines).with_context(|| {
+                format!("Error loading GTFS feed {:?}", config.path)
+            })?;
+        Ok(Self {
+            schedule,
+            loaded_at: Utc::now(),
+        })
+    }
+}
+
+impl TransitProvider for GtfsStatic {
+    /// There's no realtime data, so everything shows as scheduled
+    fn predictions(&self) -> Option<(Vec<Departure>, DateTime<Utc>)> {
+        None
+    }
+
+    fn schedules(&self, now: DateTime<Utc>) -> Vec<Departure> {
+        self.schedule.departures(now)
+    }
+
+    fn is_refreshing(&self) -> bool {
+        false
+    }
+
+    fn status(&self) -> BTreeMap<&'static str, FetchStatus> {
+        let status = FetchStatus {
+            refreshing: false,
+            failures: 0,
+            fetched_at: Some(self.loaded_at),
+            stale: false,
+        };
+        BTreeMap::from([("schedule", status)])
+    }
+
+    /// Nothing to fetch; the feed was loaded on startup
+    fn fetch_blocking(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The feed is already local, so fixtures don't apply
+    fn load_fixtures(
+        &self,
+        _dir: &Path,
+        _fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The parts of a feed that we care about
+#[derive(Debug, Default)]
+struct Schedule {
+    /// Trips on configured routes, keyed by ID
+    trips: HashMap<String, TripRecord>,
+    /// Stop times of those trips at configured stops (or their child
+    /// platforms)
+    stop_times: Vec<StopTime>,
+    /// Keyed by service ID
+    services: HashMap<String, Service>,
+    /// Timezone of the agency, which all times and dates are in
+    timezone: LocalTz,
+}
+
+impl Schedule {
+    /// Don't show departures further out than this. A countdown a day away
+    /// is just noise
+    const HORIZON: chrono::Duration = chrono::Duration::hours(3);
+
+    fn load(path: &Path, lines: &[TransitLine]) -> anyhow::Result<Self> {
+        info!("Loading GTFS feed from {path:?}");
+        let file = File::open(path)
+            .with_context(|| format!("Error opening {path:?}"))?;
+        let mut archive = ZipArchive::new(file)?;
+        let mut schedule = Self::default();
+
+        // Every agency in a feed has to share the same timezone
+        let mut timezone = None;
+        read_file(&mut archive, "agency.txt", |agency: AgencyRecord| {
+            timezone.get_or_insert(agency.agency_timezone);
+        })?
+        .context("Missing agency.txt")?;
+        schedule.timezone =
+            LocalTz::Named(timezone.context("No agency in agency.txt")?);
+
+        let routes: HashSet<&str> =
+            lines.iter().map(TransitLine::route).collect();
+        let configured_stops: HashSet<&str> = lines
+            .iter()
+            .flat_map(|line| [&*line.inbound_stop, &*line.outbound_stop])
+            .collect();
+        // Stop ID to parent station, for every stop we want
+        let mut stops: HashMap<String, Option<String>> = configured_stops
+            .iter()
+            .map(|stop| ((*stop).to_owned(), None))
+            .collect();
+
+        read_file(&mut archive, "stops.txt", |stop: StopRecord| {
+            if let Some(parent) = stop.parent_station
+                && configured_stops.contains(parent.as_str())
+            {
+                stops.insert(stop.stop_id, Some(parent));
+            }
+        })?
+        .context("Missing stops.txt")?;
+        read_file(&mut archive, "trips.txt", |trip: TripRecord| {
+            if routes.contains(trip.route_id.as_str()) {
+                schedule.trips.insert(trip.trip_id.clone(), trip);
+            }
+        })?
+        .context("Missing trips.txt")?;
+        read_file(&mut archive, "stop_times.txt", |record: StopTimeRecord| {
+            // Stops that aren't timepoints may not have a time
+            let Some(departure) = record.departure_time.or(record.arrival_time)
+            else {
+                return;
+            };
+            if let Some(parent_station) = stops.get(&record.stop_id)
+                && schedule.trips.contains_key(&record.trip_id)
+            {
+                schedule.stop_times.push(StopTime {
+                    trip_id: record.trip_id,
+                    stop_id: record.stop_id,
+                    parent_station: parent_station.clone(),
+                    arrival: record.arrival_time,
+                    departure,
+                });
+            }
+        })?
+        .context("Missing stop_times.txt")?;
+
+        // A feed needs at least one of these
+        let calendar = read_file(
+            &mut archive,
+            "calendar.txt",
+            |record: CalendarRecord| {
+                let service =
+                    schedule.services.entry(record.service_id).or_default();
+                service.days = [
+                    record.monday,
+                    record.tuesday,
+                    record.wednesday,
+                    record.thursday,
+                    record.friday,
+                    record.saturday,
+                    record.sunday,
+                ]
+                .map(|day| day == 1);
+                service.range = Some((record.start_date, record.end_date));
+            },
+        )?;
+        let calendar_dates = read_file(
+            &mut archive,
+            "calendar_dates.txt",
+            |record: CalendarDateRecord| {
+                let service =
+                    schedule.services.entry(record.service_id).or_default();
+                match record.exception_type {
+                    1 => service.added.insert(record.date),
+                    2 => service.removed.insert(record.date),
+                    _ => false,
+                };
+            },
+        )?;
+        if calendar.is_none() && calendar_dates.is_none() {
+            bail!("Missing calendar.txt and calendar_dates.txt");
+        }
+
+        info!(
+            "Loaded {} stop times on {} trips",
+            schedule.stop_times.len(),
+            schedule.trips.len()
+        );
+        Ok(schedule)
+    }
+
+    /// Get departures within the next few hours. Trips from yesterday's
+    /// service can run past midnight, and tomorrow's may be the next ones to
+    /// leave, so check all three days
+    fn departures(&self, now: DateTime<Utc>) -> Vec<Departure> {
+        let timezone = self.timezone;
+        let today = timezone.localize(now).date_naive();
+        let horizon = now + Self::HORIZON;
+        [today.pred_opt(), Some(today), today.succ_opt()]
+            .into_iter()
+            .flatten()
+            .filter_map(|date| Some((date, service_day_start(date, timezone)?)))
+            .flat_map(|(date, start)| {
+                self.stop_times.iter().filter_map(move |stop_time| {
+                    let trip = self.trips.get(&stop_time.trip_id)?;
+                    let service = self.services.get(&trip.service_id)?;
+                    let time = |seconds: u32| {
+                        start + chrono::Duration::seconds(seconds.into())
+                    };
+                    let departure_time = time(stop_time.departure);
+                    (service.is_active(date)
+                        && (now..=horizon).contains(&departure_time))
+                    .then(|| Departure {
+                        route: trip.route_id.clone(),
+                        stop: stop_time.stop_id.clone(),
+                        parent_station: stop_time.parent_station.clone(),
+                        direction_id: trip.direction_id,
+                        headsign: trip.trip_headsign.clone(),
+                        arrival_time: stop_time.arrival.map(time),
+                        departure_time,
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// A trip stopping at one of our stops. Times are seconds since the start of
+/// the service day
+#[derive(Debug)]
+struct StopTime {
+    trip_id: String,
+    stop_id: String,
+    parent_station: Option<String>,
+    arrival: Option<u32>,
+    departure: u32,
+}
+
+/// When a service runs
+#[derive(Debug, Default)]
+struct Service {
+    /// Monday first
+    days: [bool; 7],
+    /// Dates the weekly pattern applies between, inclusive. `None` if the
+    /// service is only defined by exceptions
+    range: Option<(NaiveDate, NaiveDate)>,
+    /// Exceptions
+    added: HashSet<NaiveDate>,
+    removed: HashSet<NaiveDate>,
+}
+
+impl Service {
+    fn is_active(&self, date: NaiveDate) -> bool {
+        if self.removed.contains(&date) {
+            false
+        } else if self.added.contains(&date) {
+            true
+        } else {
+            self.range
+                .is_some_and(|(start, end)| start <= date && date <= end)
+                && self.days[date.weekday().num_days_from_monday() as usize]
+        }
+    }
+}
+
+/// GTFS times are relative to noon minus 12 hours, which is midnight except on
+/// days when the clocks change
+fn service_day_start(
+    date: NaiveDate,
+    timezone: LocalTz,
+) -> Option<DateTime<Utc>> {
+    let noon = timezone.to_utc(date.and_hms_opt(12, 0, 0)?)?;
+    Some(noon - chrono::Duration::hours(12))
+}
+
+/// Parse each row of a CSV file in the archive. Returns `None` if the file
+/// isn't there, since some are optional
+fn read_file<T: DeserializeOwned>(
+    archive: &mut ZipArchive<File>,
+    name: &str,
+    mut f: impl FnMut(T),
+) -> anyhow::Result<Option<()>> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err).context(format!("Error reading {name}")),
+    };
+    for record in csv::Reader::from_reader(file).deserialize() {
+        f(record.with_context(|| format!("Error parsing {name}"))?);
+    }
+    Ok(Some(()))
+}
+
+#[derive(Debug, Deserialize)]
+struct AgencyRecord {
+    agency_timezone: Tz,
+}
+
+#[derive(Debug, Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    #[serde(default)]
+    parent_station: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TripRecord {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    #[serde(default)]
+    trip_headsign: Option<String>,
+    #[serde(default)]
+    direction_id: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    stop_id: String,
+    #[serde(default, deserialize_with = "gtfs_time")]
+    arrival_time: Option<u32>,
+    #[serde(default, deserialize_with = "gtfs_time")]
+    departure_time: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CalendarRecord {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    #[serde(deserialize_with = "gtfs_date")]
+    start_date: NaiveDate,
+    #[serde(deserialize_with = "gtfs_date")]
+    end_date: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+struct CalendarDateRecord {
+    service_id: String,
+    #[serde(deserialize_with = "gtfs_date")]
+    date: NaiveDate,
+    /// 1 means service was added on this date, 2 means removed
+    exception_type: u8,
+}
+
+/// Dates are `YYYYMMDD`
+fn gtfs_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<NaiveDate, D::Error> {
+    let date = String::deserialize(deserializer)?;
+    NaiveDate::parse_from_str(&date, "%Y%m%d")
+        .map_err(|err| serde::de::Error::custom(format!("{date:?}: {err}")))
+}
+
+/// Times are `HH:MM:SS`, as seconds past the start of the service day. Hours
+/// go past 24 for trips that run after midnight
+fn gtfs_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    let Some(time) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let parts: Vec<u32> = time
+        .trim()
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|err| serde::de::Error::custom(format!("{time:?}: {err}")))?;
+    match parts.as_slice() {
+        [hours, minutes, seconds] => {
+            Ok(Some(hours * 3600 + minutes * 60 + seconds))
+        }
+        _ => Err(serde::de::Error::custom(format!("Invalid time {time:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::test_config, http::StubTransport, transit::Transit,
+        util::fixture_now,
+    };
+    use std::{env, fs, io::Write, sync::Arc};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    /// Departures come from services running today, with exceptions applied
+    #[test]
+    fn test_schedule() {
+        let transit = transit(
+            "schedule",
+            &[
+                (
+                    "agency.txt",
+                    "agency_id,agency_name,agency_url,agency_timezone\n\
+                    A,Agency,https://example.com,America/New_York\n",
+                ),
+                (
+                    "stops.txt",
+                    "stop_id,stop_name,parent_station\n\
+                    A,Stop A,\n\
+                    B,Station B,\n\
+                    B1,Platform B1,B\n",
+                ),
+                (
+                    "trips.txt",
+                    "route_id,service_id,trip_id,trip_headsign,direction_id\n\
+                    R,weekday,t1,Downtown,0\n\
+                    R,saturday,t2,Downtown,0\n\
+                    R,saturday,t3,Downtown,0\n\
+                    R,weekday,t4,Uptown,1\n\
+                    R,saturday,t5,Uptown,1\n\
+                    X,weekday,t6,Elsewhere,0\n",
+                ),
+                (
+                    "stop_times.txt",
+                    "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                    t1,13:10:00,13:10:00,A,1\n\
+                    t2,13:20:00,13:20:00,A,1\n\
+                    t3,14:00:00,14:00:00,A,1\n\
+                    t4,13:30:00,13:30:00,B1,1\n\
+                    t5,12:50:00,12:50:00,B1,1\n\
+                    t5,13:50:00,13:50:00,B1,2\n\
+                    t6,13:06:00,13:06:00,A,1\n",
+                ),
+                (
+                    "calendar.txt",
+                    "service_id,monday,tuesday,wednesday,thursday,friday,\
+                    saturday,sunday,start_date,end_date\n\
+                    weekday,1,1,1,1,1,0,0,20240101,20241231\n\
+                    saturday,0,0,0,0,0,1,0,20240101,20241231\n",
+                ),
+                // Friday is a holiday, running the Saturday schedule
+                (
+                    "calendar_dates.txt",
+                    "service_id,date,exception_type\n\
+                    weekday,20240524,2\n\
+                    saturday,20240524,1\n",
+                ),
+            ],
+        );
+
+        let now = fixture_now();
+        let predictions = transit.predictions(now);
+        assert_eq!(predictions.fetched_at, None);
+        let line = &predictions.lines[0];
+        assert_eq!(line.inbound.to_string(), "15,55m*");
+        // Tomorrow is a real Saturday, but its early trip is too far off to
+        // show
+        assert_eq!(line.outbound.to_string(), "45m*");
+    }
+
+    /// Times are in the agency's timezone, not the one the screen shows or
+    /// the system's. It's 10:05 in Los Angeles, so the 13:15 departure is
+    /// hours away, even though it would be 10 minutes away in Boston
+    #[test]
+    fn test_agency_timezone() {
+        let transit = transit(
+            "agency_timezone",
+            &[
+                (
+                    "agency.txt",
+                    "agency_id,agency_name,agency_url,agency_timezone\n\
+                    A,Agency,https://example.com,America/Los_Angeles\n",
+                ),
+                ("stops.txt", "stop_id,stop_name\nA,Stop A\nB,Stop B\n"),
+                (
+                    "trips.txt",
+                    "route_id,service_id,trip_id\n\
+                    R,daily,t1\n\
+                    R,daily,t2\n",
+                ),
+                (
+                    "stop_times.txt",
+                    "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                    t1,10:15:00,10:15:00,A,1\n\
+                    t2,13:15:00,13:15:00,B,1\n",
+                ),
+                (
+                    "calendar.txt",
+                    "service_id,monday,tuesday,wednesday,thursday,friday,\
+                    saturday,sunday,start_date,end_date\n\
+                    daily,1,1,1,1,1,1,1,20240101,20241231\n",
+                ),
+            ],
+        );
+
+        let predictions = transit.predictions(fixture_now());
+        let line = &predictions.lines[0];
+        assert_eq!(line.inbound.to_string(), "10m*");
+        assert_eq!(line.outbound.to_string(), "");
+    }
+
+    /// Build a transit client for a single line, R from stop A to B, reading
+    /// a feed with the given files
+    fn transit(name: &str, files: &[(&str, &str)]) -> Transit {
+        let dir = env::temp_dir().join(format!(
+            "goldfinger-test-gtfs-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gtfs.zip");
+        write_feed(&path, files);
+
+        let mut config = test_config();
+        config.transit_provider = serde_json::from_value(serde_json::json!({
+            "kind": "gtfs_static",
+            "path": path,
+        }))
+        .unwrap();
+        config.transit_lines = serde_json::from_str(
+            r#"[{"name": "R", "inbound_stop": "A", "outbound_stop": "B"}]"#,
+        )
+        .unwrap();
+        // Nothing should hit the network
+        let transit =
+            Transit::new(&config, Arc::new(StubTransport::default())).unwrap();
+        // The feed is loaded up front, so it's not needed anymore
+        fs::remove_dir_all(&dir).unwrap();
+        transit
+    }
+
+    fn write_feed(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+}
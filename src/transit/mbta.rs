@@ -0,0 +1,894 @@
+//! Client for the [MBTA v3 API](https://api-v3.mbta.com). Predictions,
+//! schedules, and service alerts are all polled, and predictions can be
+//! streamed instead
+
+mod stream;
+
+use self::stream::PredictionStream;
+use super::{Departure, Transit, TransitLine, TransitProvider};
+use crate::{
+    config::Config,
+    http::Transport,
+    util::{ApiFetcher, FetchStatus},
+};
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::America::New_York as Boston;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub struct Mbta {
+    predictions: ApiFetcher<ApiPredictions>,
+    /// Fallback for when there are no realtime predictions, which is common
+    /// for buses early in the morning
+    schedules: ApiFetcher<ApiSchedules>,
+    /// Service alerts (detours, suspensions, etc.) for our routes
+    alerts: ApiFetcher<ApiAlerts>,
+    /// Service alerts for our stops. Stop closures often only name the stop,
+    /// so they don't turn up when filtering by route
+    stop_alerts: ApiFetcher<ApiAlerts>,
+    /// Live predictions pushed from the server, if enabled. Whenever the
+    /// stream is down or has gone quiet, we fall back to polling
+    stream: Option<PredictionStream>,
+}
+
+impl Mbta {
+    /// Time between fetches. The stream has to have been heard from within
+    /// this long too
+    const DATA_TTL: Duration = Duration::from_secs(30);
+    /// Schedules rarely change, but each fetch only covers the next
+    /// [Self::SCHEDULE_LIMIT] departures, so refetch before they run out
+    const SCHEDULE_TTL: Duration = Duration::from_secs(15 * 60);
+    /// Most departures to fetch schedules for, across all stops. The full day
+    /// is thousands of entries at a busy station
+    const SCHEDULE_LIMIT: usize = 100;
+    /// Trips that start before this hour are part of the previous service
+    /// day
+    const SERVICE_DAY_START_HOUR: u32 = 3;
+    /// Alerts don't change much minute to minute
+    const ALERTS_TTL: Duration = Duration::from_secs(2 * 60);
+    /// Alerts below this severity (on MBTA's 0-10 scale) aren't worth
+    /// showing. Elevator outages and the like are in the 1-2 range
+    const MIN_ALERT_SEVERITY: u8 = 3;
+    /// Delays are rated by their length, and the predictions already account
+    /// for them, so only flag the long ones
+    const MIN_DELAY_SEVERITY: u8 = 7;
+
+    /// Fixture file (within the fixture directory) for predictions
+    const PREDICTIONS_FIXTURE: &'static str = "predictions.json";
+    /// Fixture file (within the fixture directory) for schedules
+    const SCHEDULES_FIXTURE: &'static str = "schedules.json";
+    /// Fixture file (within the fixture directory) for alerts
+    const ALERTS_FIXTURE: &'static str = "alerts.json";
+    /// Fixture file (within the fixture directory) for stop alerts
+    const STOP_ALERTS_FIXTURE: &'static str = "stop_alerts.json";
+
+    pub fn new(config: &Config, transport: Arc<dyn Transport>) -> Self {
+        let lines = &config.transit_lines;
+        let mut predictions = ApiFetcher::new(
+            api_url("predictions", lines),
+            Self::DATA_TTL,
+            Arc::clone(&transport),
+        )
+        .with_retry_policy(Transit::RETRY_POLICY)
+        .with_cache(config.cache_dir.as_deref(), "predictions");
+        let mut schedules = ApiFetcher::new(
+            api_url("schedules", lines),
+            Self::SCHEDULE_TTL,
+            Arc::clone(&transport),
+        )
+        .with_query(schedules_query)
+        .with_retry_policy(Transit::RETRY_POLICY)
+        .with_cache(config.cache_dir.as_deref(), "schedules");
+        let routes = lines.iter().map(TransitLine::route);
+        let mut alerts = ApiFetcher::new(
+            alerts_url("route", routes),
+            Self::ALERTS_TTL,
+            Arc::clone(&transport),
+        )
+        .with_retry_policy(Transit::RETRY_POLICY)
+        .with_cache(config.cache_dir.as_deref(), "alerts");
+        let stops = lines.iter().flat_map(|line| {
+            [line.inbound_stop.as_str(), line.outbound_stop.as_str()]
+        });
+        let mut stop_alerts = ApiFetcher::new(
+            alerts_url("stop", stops),
+            Self::ALERTS_TTL,
+            Arc::clone(&transport),
+        )
+        .with_retry_policy(Transit::RETRY_POLICY)
+        .with_cache(config.cache_dir.as_deref(), "stop_alerts");
+        // The API works without a key, just with a lower rate limit
+        if let Some(api_key) = &config.mbta_api_key {
+            predictions =
+                predictions.with_header("x-api-key", api_key.as_str());
+            schedules = schedules.with_header("x-api-key", api_key.as_str());
+            alerts = alerts.with_header("x-api-key", api_key.as_str());
+            stop_alerts =
+                stop_alerts.with_header("x-api-key", api_key.as_str());
+        }
+        let stream = config.transit_streaming.then(|| {
+            let headers = config
+                .mbta_api_key
+                .iter()
+                .map(|api_key| ("x-api-key".to_owned(), api_key.clone()))
+                .collect();
+            PredictionStream::start(
+                api_url("predictions", lines),
+                headers,
+                transport,
+            )
+        });
+        Self {
+            predictions,
+            schedules,
+            alerts,
+            stop_alerts,
+            stream,
+        }
+    }
+
+    /// The prediction stream, if it's enabled and up to date
+    fn live_stream(&self) -> Option<&PredictionStream> {
+        self.stream
+            .as_ref()
+            .filter(|stream| stream.is_fresh(Self::DATA_TTL))
+    }
+}
+
+impl TransitProvider for Mbta {
+    fn predictions(&self) -> Option<(Vec<Departure>, DateTime<Utc>)> {
+        let (data, fetched_at) = self
+            .live_stream()
+            .and_then(PredictionStream::data)
+            .or_else(|| self.predictions.data())?;
+        Some((data.departures(), fetched_at))
+    }
+
+    fn schedules(&self, _now: DateTime<Utc>) -> Vec<Departure> {
+        self.schedules
+            .data()
+            .map(|(schedules, _)| schedules.departures())
+            .unwrap_or_default()
+    }
+
+    fn alert(&self, line: &TransitLine, now: DateTime<Utc>) -> Option<String> {
+        // If there are several alerts, show the worst one. An alert can be in
+        // both responses, but that doesn't matter here
+        [&self.alerts, &self.stop_alerts]
+            .into_iter()
+            .filter_map(|alerts| {
+                alerts.with_data(|alerts, _| alerts.worst(line, now))?
+            })
+            .max_by_key(|(severity, _)| *severity)
+            .map(|(_, label)| label.to_owned())
+    }
+
+    fn is_refreshing(&self) -> bool {
+        self.predictions.is_fetching()
+    }
+
+    fn status(&self) -> BTreeMap<&'static str, FetchStatus> {
+        let predictions = if let Some(stream) = self.live_stream()
+            && let Some(updated_at) = stream.updated_at()
+        {
+            FetchStatus {
+                refreshing: false,
+                failures: stream.failures(),
+                fetched_at: Some(updated_at),
+                stale: false,
+            }
+        } else {
+            (&self.predictions).into()
+        };
+        BTreeMap::from([
+            ("predictions", predictions),
+            ("schedules", (&self.schedules).into()),
+            ("alerts", (&self.alerts).into()),
+            ("stop_alerts", (&self.stop_alerts).into()),
+        ])
+    }
+
+    fn fetch_blocking(&self) -> anyhow::Result<()> {
+        self.predictions.fetch_blocking()?;
+        self.schedules.fetch_blocking()?;
+        self.alerts.fetch_blocking()?;
+        self.stop_alerts.fetch_blocking()
+    }
+
+    fn load_fixtures(
+        &self,
+        dir: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.predictions
+            .load_file(&dir.join(Self::PREDICTIONS_FIXTURE), fetched_at)?;
+        self.schedules
+            .load_file(&dir.join(Self::SCHEDULES_FIXTURE), fetched_at)?;
+        self.alerts
+            .load_file(&dir.join(Self::ALERTS_FIXTURE), fetched_at)?;
+        self.stop_alerts
+            .load_file(&dir.join(Self::STOP_ALERTS_FIXTURE), fetched_at)
+    }
+}
+
+/// Limit schedules to departures from now on, soonest first. The API wants
+/// Boston time, and the service day runs past midnight, so 1:30 AM is 25:30
+/// on the previous day
+fn schedules_query(now: DateTime<Utc>) -> String {
+    let now = now.with_timezone(&Boston);
+    let (date, hour) = if now.hour() < Mbta::SERVICE_DAY_START_HOUR {
+        (now.date_naive() - chrono::Days::new(1), now.hour() + 24)
+    } else {
+        (now.date_naive(), now.hour())
+    };
+    format!(
+        "&filter[date]={date}&filter[min_time]={hour:02}:{:02}\
+        &sort=departure_time&page[limit]={}",
+        now.minute(),
+        Mbta::SCHEDULE_LIMIT,
+    )
+}
+
+/// Build a query for active alerts on the given routes or stops (`filter` is
+/// `route` or `stop`). The API ANDs filters together, and route-wide alerts
+/// (e.g. a detour) don't list every stop they affect, while stop closures
+/// often don't list a route, so each needs its own query. Alerts are checked
+/// against each line's route and stops afterward. Include stops so we can map
+/// child platforms to parent stations
+fn alerts_url<'a>(filter: &str, ids: impl Iterator<Item = &'a str>) -> String {
+    format!(
+        "https://api-v3.mbta.com/alerts?filter[{filter}]={}\
+        &filter[datetime]=NOW&include=stops",
+        ids.unique().format(",")
+    )
+}
+
+/// Build a query against a departure endpoint (`predictions` or `schedules`)
+/// for all configured lines. Filtering by route here keeps the response small
+/// at busy stops; everything is filtered again per line when building
+/// predictions
+fn api_url(endpoint: &str, lines: &[TransitLine]) -> String {
+    let stops = lines
+        .iter()
+        .flat_map(|line| [&line.inbound_stop, &line.outbound_stop])
+        .unique();
+    let routes = lines.iter().map(TransitLine::route).unique();
+    // Include stops so we can map child platforms to parent stations, and
+    // trips for headsigns
+    let mut url = format!(
+        "https://api-v3.mbta.com/{endpoint}?filter[stop]={}\
+        &filter[route]={}&include=stop,trip",
+        stops.format(","),
+        routes.format(","),
+    );
+    // The API only takes a single direction, so we can only filter on it if
+    // every line wants the same one
+    let direction_ids: Vec<_> = lines
+        .iter()
+        .flat_map(|line| {
+            [line.inbound_direction_id, line.outbound_direction_id]
+        })
+        .unique()
+        .collect();
+    if let [Some(direction_id)] = direction_ids.as_slice() {
+        url.push_str(&format!("&filter[direction_id]={direction_id}"));
+    }
+    url
+}
+
+/// <https://api-v3.mbta.com/docs/swagger/index.html#/Prediction/ApiWeb_PredictionController_index>
+#[derive(Clone, Debug, Deserialize)]
+struct ApiPredictions {
+    data: Vec<Prediction>,
+    /// Related resources from `include=stop,trip`
+    #[serde(default)]
+    included: Vec<Included>,
+}
+
+impl ApiPredictions {
+    /// Convert to departures, with parent stations and headsigns filled in
+    /// from the included resources
+    fn departures(&self) -> Vec<Departure> {
+        let parent_stations = parent_stations(&self.included);
+        let headsigns = self.headsigns();
+        self.data
+            .iter()
+            .filter_map(|prediction| {
+                let relationships = &prediction.relationships;
+                let stop = relationships.stop.data.id.as_str();
+                let headsign = relationships
+                    .trip
+                    .data
+                    .as_ref()
+                    .and_then(|trip| headsigns.get(trip.id.as_str()));
+                Some(Departure {
+                    route: relationships.route.data.id.clone(),
+                    stop: stop.to_owned(),
+                    parent_station: parent_stations
+                        .get(stop)
+                        .map(|parent| (*parent).to_owned()),
+                    direction_id: prediction.attributes.direction_id,
+                    headsign: headsign.map(|headsign| (*headsign).to_owned()),
+                    arrival_time: prediction.attributes.arrival_time,
+                    // Departure time will be empty if the stop is being
+                    // skipped
+                    departure_time: prediction.attributes.departure_time?,
+                })
+            })
+            .collect()
+    }
+
+    /// Map of trip ID to headsign
+    fn headsigns(&self) -> HashMap<&str, &str> {
+        self.included
+            .iter()
+            .filter(|resource| resource.kind == "trip")
+            .filter_map(|trip| {
+                Some((trip.id.as_str(), trip.attributes.headsign.as_deref()?))
+            })
+            .collect()
+    }
+}
+
+/// Map of stop ID to parent station ID, for included stops that have one
+fn parent_stations(included: &[Included]) -> HashMap<&str, &str> {
+    included
+        .iter()
+        .filter(|resource| resource.kind == "stop")
+        .filter_map(|stop| {
+            let parent = stop.relationships.parent_station.data.as_ref()?;
+            Some((stop.id.as_str(), parent.id.as_str()))
+        })
+        .collect()
+}
+
+/// <https://api-v3.mbta.com/docs/swagger/index.html#/Schedule/ApiWeb_ScheduleController_index>
+///
+/// Schedules have the same shape as predictions, as far as we're concerned
+type ApiSchedules = ApiPredictions;
+
+/// <https://api-v3.mbta.com/docs/swagger/index.html#/Alert/ApiWeb_AlertController_index>
+#[derive(Clone, Debug, Deserialize)]
+struct ApiAlerts {
+    data: Vec<Alert>,
+    /// Related stops from `include=stops`
+    #[serde(default)]
+    included: Vec<Included>,
+}
+
+impl ApiAlerts {
+    /// Severity and label of the worst alert that's worth showing for a line
+    fn worst(
+        &self,
+        line: &TransitLine,
+        now: DateTime<Utc>,
+    ) -> Option<(u8, &'static str)> {
+        let parent_stations = parent_stations(&self.included);
+        self.data
+            .iter()
+            .filter(|alert| {
+                alert.is_severe()
+                    && alert.is_active(now)
+                    && alert.affects(line, &parent_stations)
+            })
+            .filter_map(|alert| {
+                Some((alert.attributes.severity, alert.label()?))
+            })
+            .max_by_key(|(severity, _)| *severity)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Alert {
+    attributes: AlertAttributes,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AlertAttributes {
+    /// e.g. `DETOUR` or `SUSPENSION`
+    effect: String,
+    /// 0-10, 10 being the worst
+    severity: u8,
+    active_period: Vec<ActivePeriod>,
+    informed_entity: Vec<InformedEntity>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ActivePeriod {
+    start: DateTime<Utc>,
+    /// `None` means until further notice
+    end: Option<DateTime<Utc>>,
+}
+
+/// Something an alert applies to. Any missing field is a wildcard, but at
+/// least one has to be given
+#[derive(Clone, Debug, Deserialize)]
+struct InformedEntity {
+    route: Option<String>,
+    stop: Option<String>,
+}
+
+impl Alert {
+    /// Short label for the display, if this is the kind of alert that
+    /// changes whether it's worth going to the stop
+    fn label(&self) -> Option<&'static str> {
+        match self.attributes.effect.as_str() {
+            "DETOUR" => Some("DETOUR"),
+            "SUSPENSION" => Some("SUSPENDED"),
+            "SHUTTLE" => Some("SHUTTLE"),
+            "CANCELLATION" | "NO_SERVICE" => Some("NO SERVICE"),
+            "STOP_CLOSURE" | "STATION_CLOSURE" => Some("CLOSED"),
+            "STOP_MOVE" | "STOP_MOVED" => Some("STOP MOVED"),
+            "SNOW_ROUTE" => Some("SNOW ROUTE"),
+            "DELAY" => Some("DELAY"),
+            _ => None,
+        }
+    }
+
+    /// Is the alert bad enough to show?
+    fn is_severe(&self) -> bool {
+        let min_severity = if self.attributes.effect == "DELAY" {
+            Mbta::MIN_DELAY_SEVERITY
+        } else {
+            Mbta::MIN_ALERT_SEVERITY
+        };
+        self.attributes.severity >= min_severity
+    }
+
+    /// Is the alert in effect right now? The query only asks for active
+    /// alerts, but the response may be a few minutes old
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.attributes.active_period.iter().any(|period| {
+            period.start <= now && period.end.is_none_or(|end| now < end)
+        })
+    }
+
+    /// Does the alert apply to the line's route and one of its stops? Stop
+    /// closures often only name the stop. A stop matches if it's the
+    /// configured stop itself, or one of its child platforms
+    fn affects(
+        &self,
+        line: &TransitLine,
+        parent_stations: &HashMap<&str, &str>,
+    ) -> bool {
+        self.attributes.informed_entity.iter().any(|entity| {
+            let route = entity.route.as_deref();
+            let stop = entity.stop.as_deref();
+            (route.is_some() || stop.is_some())
+                && route.is_none_or(|route| route == line.route())
+                && stop.is_none_or(|stop| {
+                    let parent = parent_stations.get(stop).copied();
+                    [&line.inbound_stop, &line.outbound_stop].into_iter().any(
+                        |configured| {
+                            stop == configured
+                                || parent == Some(configured.as_str())
+                        },
+                    )
+                })
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Included {
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
+    #[serde(default)]
+    attributes: IncludedAttributes,
+    #[serde(default)]
+    relationships: IncludedRelationships,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct IncludedAttributes {
+    /// Only on trips
+    headsign: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct IncludedRelationships {
+    #[serde(default)]
+    parent_station: OptionalRelationship,
+}
+
+/// Relationship that may be null, e.g. a stop without a parent station
+#[derive(Clone, Debug, Default, Deserialize)]
+struct OptionalRelationship {
+    data: Option<RelationshipData>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Prediction {
+    attributes: Attributes,
+    relationships: Relationships,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Attributes {
+    arrival_time: Option<DateTime<Utc>>,
+    departure_time: Option<DateTime<Utc>>,
+    direction_id: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Relationships {
+    route: Relationship,
+    stop: Relationship,
+    #[serde(default)]
+    trip: OptionalRelationship,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Relationship {
+    data: RelationshipData,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RelationshipData {
+    id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::test_config,
+        http::{Stub, StubTransport},
+        util::fixture_now,
+    };
+
+    #[test]
+    fn test_predictions() {
+        let config = test_config();
+        let config = Config {
+            mbta_api_key: Some("hunter2".into()),
+            ..config
+        };
+        let transport = StubTransport::new([
+            Stub::ok(include_str!("../../fixtures/predictions.json")),
+            Stub::ok(include_str!("../../fixtures/schedules.json")),
+            Stub::ok(include_str!("../../fixtures/alerts.json")),
+            Stub::ok(include_str!("../../fixtures/stop_alerts.json")),
+        ]);
+        let transit =
+            Transit::new(&config, Arc::new(transport.clone())).unwrap();
+        let before = Utc::now();
+        transit.fetch_blocking().unwrap();
+        let mut requests = transport.requests();
+        // Schedules are filtered from the time of the request, which may have
+        // ticked over to the next minute
+        let query = [before, Utc::now()]
+            .map(schedules_query)
+            .into_iter()
+            .find(|query| requests[1].ends_with(query))
+            .expect("Schedules request has no time filter");
+        let schedules_len = requests[1].len() - query.len();
+        requests[1].truncate(schedules_len);
+        assert_eq!(
+            requests,
+            [
+                "https://api-v3.mbta.com/predictions\
+                ?filter[stop]=2545,2570,2435,2455&filter[route]=87,88\
+                &include=stop,trip",
+                "https://api-v3.mbta.com/schedules\
+                ?filter[stop]=2545,2570,2435,2455&filter[route]=87,88\
+                &include=stop,trip",
+                "https://api-v3.mbta.com/alerts\
+                ?filter[route]=87,88&filter[datetime]=NOW&include=stops",
+                "https://api-v3.mbta.com/alerts\
+                ?filter[stop]=2545,2570,2435,2455&filter[datetime]=NOW\
+                &include=stops",
+            ]
+        );
+        for headers in transport.request_headers() {
+            assert_eq!(
+                headers,
+                [("x-api-key".to_owned(), "hunter2".to_owned())]
+            );
+        }
+
+        let now = fixture_now();
+        let lines: Vec<_> = transit
+            .predictions(now)
+            .lines
+            .into_iter()
+            .map(|line| {
+                (
+                    line.name,
+                    line.alert,
+                    line.inbound.to_string(),
+                    line.outbound.to_string(),
+                )
+            })
+            .collect();
+        // 87 has a minor delay and an expired stop closure, neither of which
+        // is shown. 88 is detoured, but the closure of one of its stops, which
+        // doesn't name the route, is worse
+        assert_eq!(
+            lines,
+            [
+                ("87".into(), None, "4,19m".into(), "11,27m".into()),
+                (
+                    "88".into(),
+                    Some("CLOSED".into()),
+                    "7,24m".into(),
+                    "2,16m".into()
+                ),
+            ]
+        );
+    }
+
+    /// Schedules start at the current minute of the service day
+    #[test]
+    fn test_schedules_query() {
+        assert_eq!(
+            schedules_query(fixture_now()),
+            "&filter[date]=2024-05-24&filter[min_time]=13:05\
+            &sort=departure_time&page[limit]=100"
+        );
+        let after_midnight = "2024-05-25T01:30:00-04:00".parse().unwrap();
+        assert_eq!(
+            schedules_query(after_midnight),
+            "&filter[date]=2024-05-24&filter[min_time]=25:30\
+            &sort=departure_time&page[limit]=100"
+        );
+    }
+
+    /// Alerts can name the route, a stop, or both
+    #[test]
+    fn test_alert_affects() {
+        let line = &test_config().transit_lines[0];
+        let parent_stations = HashMap::from([("70068", "2545")]);
+        let affects = |entity: serde_json::Value| {
+            let alert: Alert = serde_json::from_value(serde_json::json!({
+                "attributes": {
+                    "effect": "STOP_CLOSURE",
+                    "severity": 5,
+                    "active_period": [],
+                    "informed_entity": [entity],
+                }
+            }))
+            .unwrap();
+            alert.affects(line, &parent_stations)
+        };
+
+        assert!(affects(serde_json::json!({"route": "87"})));
+        assert!(affects(serde_json::json!({"route": "87", "stop": "2570"})));
+        assert!(!affects(serde_json::json!({"route": "87", "stop": "1"})));
+        assert!(!affects(serde_json::json!({"route": "88"})));
+        assert!(affects(serde_json::json!({"stop": "2545"})));
+        assert!(!affects(serde_json::json!({"stop": "1"})));
+        // Child platform of a configured stop
+        assert!(affects(serde_json::json!({"stop": "70068"})));
+        // Something has to be named
+        assert!(!affects(serde_json::json!({})));
+    }
+
+    /// Predictions from a child platform match a configured parent station
+    #[test]
+    fn test_parent_station() {
+        let transit = transit(
+            r#"[{"name": "Red", "inbound_stop": "place-harsq", "outbound_stop": "place-cntsq"}]"#,
+            api_response(
+                &[
+                    api_prediction("Red", "70068", 0, "t1", 3),
+                    api_prediction("Red", "place-cntsq", 1, "t2", 5),
+                ],
+                &[
+                    api_stop("70068", "place-harsq"),
+                    api_stop("70069", "place-cntsq"),
+                ],
+            ),
+            api_response(&[], &[]),
+        );
+        let predictions = transit.predictions(fixture_now());
+        let line = &predictions.lines[0];
+        assert_eq!(line.inbound.to_string(), "3m");
+        assert_eq!(line.outbound.to_string(), "5m");
+    }
+
+    /// An alert on a child platform applies to a configured parent station
+    #[test]
+    fn test_parent_station_alert() {
+        let mut config = test_config();
+        config.transit_lines = serde_json::from_str(
+            r#"[{"name": "Red", "inbound_stop": "place-harsq", "outbound_stop": "place-cntsq"}]"#,
+        )
+        .unwrap();
+        let stop_alerts = api_response(
+            &[r#"{
+                "attributes": {
+                    "effect": "STATION_CLOSURE",
+                    "severity": 7,
+                    "active_period": [{"start": "2024-05-24T05:00:00-04:00", "end": null}],
+                    "informed_entity": [{"stop": "70068"}]
+                }
+            }"#
+            .to_owned()],
+            &[api_stop("70068", "place-harsq")],
+        );
+        let transport = StubTransport::new([
+            Stub::ok(api_response(&[], &[])),
+            Stub::ok(api_response(&[], &[])),
+            Stub::ok(r#"{"data": []}"#),
+            Stub::ok(stop_alerts),
+        ]);
+        let transit = Transit::new(&config, Arc::new(transport)).unwrap();
+        transit.fetch_blocking().unwrap();
+        let predictions = transit.predictions(fixture_now());
+        assert_eq!(predictions.lines[0].alert.as_deref(), Some("CLOSED"));
+    }
+
+    /// Short delays are already in the predictions, so only long ones are
+    /// flagged
+    #[test]
+    fn test_delay_alert() {
+        let delay = |route: &str, severity: u8| {
+            format!(
+                r#"{{
+                    "attributes": {{
+                        "effect": "DELAY",
+                        "severity": {severity},
+                        "active_period": [{{"start": "2024-05-24T05:00:00-04:00", "end": null}}],
+                        "informed_entity": [{{"route": "{route}"}}]
+                    }}
+                }}"#
+            )
+        };
+        let transport = StubTransport::new([
+            Stub::ok(api_response(&[], &[])),
+            Stub::ok(api_response(&[], &[])),
+            Stub::ok(api_response(&[delay("87", 5), delay("88", 7)], &[])),
+            Stub::ok(r#"{"data": []}"#),
+        ]);
+        let transit =
+            Transit::new(&test_config(), Arc::new(transport)).unwrap();
+        transit.fetch_blocking().unwrap();
+        let predictions = transit.predictions(fixture_now());
+        assert_eq!(predictions.lines[0].alert, None);
+        assert_eq!(predictions.lines[1].alert.as_deref(), Some("DELAY"));
+    }
+
+    /// One stop can serve both directions, and several routes
+    #[test]
+    fn test_direction_filters() {
+        let transit = transit(
+            r#"[
+                {
+                    "name": "Harvard",
+                    "route": "1",
+                    "inbound_stop": "110",
+                    "outbound_stop": "110",
+                    "inbound_direction_id": 1,
+                    "outbound_direction_id": 0,
+                    "outbound_headsign": "Nubian"
+                }
+            ]"#,
+            api_response(
+                &[
+                    api_prediction("1", "110", 1, "t1", 3),
+                    api_prediction("1", "110", 0, "t2", 4),
+                    // Short-turn trip, filtered out by headsign
+                    api_prediction("1", "110", 0, "t3", 6),
+                    api_prediction("1", "110", 0, "t4", 8),
+                    // Different route at the same stop
+                    api_prediction("47", "110", 1, "t5", 2),
+                ],
+                &[
+                    api_trip("t2", "Nubian Station"),
+                    api_trip("t3", "Mass Ave"),
+                    api_trip("t4", "Nubian Station"),
+                ],
+            ),
+            api_response(&[], &[]),
+        );
+        let predictions = transit.predictions(fixture_now());
+        let line = &predictions.lines[0];
+        assert_eq!(line.inbound.to_string(), "3m");
+        assert_eq!(line.outbound.to_string(), "4,8m");
+    }
+
+    /// A direction with no predictions shows its schedule, marked with a `*`
+    #[test]
+    fn test_schedule_fallback() {
+        let transit = transit(
+            r#"[{"name": "87", "inbound_stop": "2545", "outbound_stop": "2570"}]"#,
+            api_response(
+                &[
+                    api_prediction("87", "2545", 1, "t1", 4),
+                    // Already left, so it doesn't count
+                    api_prediction("87", "2570", 0, "t2", -2),
+                ],
+                &[],
+            ),
+            api_response(
+                &[
+                    api_prediction("87", "2545", 1, "t1", 5),
+                    api_prediction("87", "2570", 0, "t2", -1),
+                    api_prediction("87", "2570", 0, "t3", 12),
+                    api_prediction("87", "2570", 0, "t4", 30),
+                ],
+                &[],
+            ),
+        );
+        let predictions = transit.predictions(fixture_now());
+        let line = &predictions.lines[0];
+        assert_eq!(line.inbound.to_string(), "4m");
+        assert_eq!(line.outbound.to_string(), "12,30m*");
+    }
+
+    /// Build a transit client for the given lines (as JSON), and load API
+    /// responses into it
+    fn transit(lines: &str, predictions: String, schedules: String) -> Transit {
+        let mut config = test_config();
+        config.transit_lines = serde_json::from_str(lines).unwrap();
+        let transport = StubTransport::new([
+            Stub::ok(predictions),
+            Stub::ok(schedules),
+            Stub::ok(r#"{"data": []}"#),
+        ]);
+        let transit = Transit::new(&config, Arc::new(transport)).unwrap();
+        transit.fetch_blocking().unwrap();
+        transit
+    }
+
+    /// JSON:API response body with the given resources
+    fn api_response(data: &[String], included: &[String]) -> String {
+        format!(
+            r#"{{"data": [{}], "included": [{}]}}"#,
+            data.join(","),
+            included.join(",")
+        )
+    }
+
+    fn api_prediction(
+        route: &str,
+        stop: &str,
+        direction_id: u8,
+        trip: &str,
+        minutes: i64,
+    ) -> String {
+        let time = fixture_now() + chrono::Duration::minutes(minutes);
+        format!(
+            r#"{{
+                "attributes": {{
+                    "arrival_time": null,
+                    "departure_time": "{}",
+                    "direction_id": {direction_id}
+                }},
+                "relationships": {{
+                    "route": {{"data": {{"id": "{route}", "type": "route"}}}},
+                    "stop": {{"data": {{"id": "{stop}", "type": "stop"}}}},
+                    "trip": {{"data": {{"id": "{trip}", "type": "trip"}}}}
+                }}
+            }}"#,
+            time.to_rfc3339()
+        )
+    }
+
+    fn api_stop(id: &str, parent: &str) -> String {
+        format!(
+            r#"{{
+                "type": "stop",
+                "id": "{id}",
+                "relationships": {{
+                    "parent_station": {{"data": {{"id": "{parent}", "type": "stop"}}}}
+                }}
+            }}"#
+        )
+    }
+
+    fn api_trip(id: &str, headsign: &str) -> String {
+        format!(
+            r#"{{"type": "trip", "id": "{id}", "attributes": {{"headsign": "{headsign}"}}}}"#
+        )
+    }
+}
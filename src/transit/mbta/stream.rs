@@ -0,0 +1,573 @@
+//! Live predictions from the MBTA streaming API. Instead of polling, the
+//! server pushes changes to the prediction set as server-sent events
+
+use super::{ApiPredictions, Included, Prediction};
+use crate::{
+    http::Transport,
+    util::{is_expired, RetryPolicy},
+};
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, trace, warn};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A background connection to a streaming endpoint, with the current
+/// prediction set kept in memory
+#[derive(Debug)]
+pub struct PredictionStream {
+    state: Arc<RwLock<StreamState>>,
+    /// Set on drop to tell the background thread to quit
+    stop: Arc<AtomicBool>,
+}
+
+/// Everything the server has told us
+#[derive(Debug, Default)]
+struct StreamState {
+    /// Keyed by ID
+    predictions: HashMap<String, Prediction>,
+    /// Keyed by (type, ID)
+    included: HashMap<(String, String), Included>,
+    /// When we last heard from the server. `None` until the initial `reset`,
+    /// and again once the connection drops
+    updated_at: Option<DateTime<Utc>>,
+    /// Consecutive failed connections
+    failures: u32,
+}
+
+impl PredictionStream {
+    /// Back off on failures, same as polling
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        initial_delay: Duration::from_secs(5),
+        max_delay: Duration::from_secs(5 * 60),
+        multiplier: 2.0,
+        jitter: 0.2,
+    };
+    /// Reconnect this often even if the connection seems healthy. We can't
+    /// time out individual reads, so this is what keeps a silently dead
+    /// connection from hanging us forever. Every new connection starts with a
+    /// full `reset`, so nothing is lost. This is routine, so it's only logged
+    /// at debug level
+    const MAX_CONNECTION_AGE: Duration = Duration::from_secs(3 * 60);
+    /// Wait at least this long between connections, so a server that hangs up
+    /// right away doesn't put us in a hot loop
+    const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+    /// Connect to the URL and start streaming on a background thread
+    pub fn start(
+        url: String,
+        headers: Vec<(String, String)>,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        let state: Arc<RwLock<StreamState>> = Default::default();
+        let stop: Arc<AtomicBool> = Default::default();
+
+        let thread_state = Arc::clone(&state);
+        let thread_stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                let mut received = false;
+                let connected_at = Instant::now();
+                let result = connect(
+                    &*transport,
+                    &url,
+                    &headers,
+                    &thread_state,
+                    &thread_stop,
+                    &mut received,
+                );
+                let failures = {
+                    let Ok(mut state) = thread_state.write() else {
+                        error!("Prediction stream state lock is poisoned");
+                        return;
+                    };
+                    state.disconnect(received);
+                    state.failures
+                };
+                match result {
+                    Ok(()) => debug!("Prediction stream from {url} closed"),
+                    Err(err)
+                        if connected_at.elapsed()
+                            >= Self::MAX_CONNECTION_AGE =>
+                    {
+                        debug!(
+                            "Reconnecting to prediction stream {url} after \
+                            {:?}: {err:#}",
+                            Self::MAX_CONNECTION_AGE
+                        );
+                    }
+                    Err(err) => warn!(
+                        "Error streaming predictions from {url} ({failures} \
+                        consecutive failures): {err:#}"
+                    ),
+                }
+                let delay = if failures == 0 {
+                    Self::MIN_RECONNECT_DELAY
+                } else {
+                    Self::RETRY_POLICY.delay(failures)
+                };
+                thread::sleep(delay);
+            }
+        });
+
+        Self { state, stop }
+    }
+
+    /// Get the current prediction set, and when we last heard from the
+    /// server. `None` if we haven't gotten the initial set yet, or the
+    /// connection is down
+    pub fn data(&self) -> Option<(ApiPredictions, DateTime<Utc>)> {
+        let state = self.state.read().ok()?;
+        let updated_at = state.updated_at?;
+        let data = ApiPredictions {
+            data: state.predictions.values().cloned().collect(),
+            included: state.included.values().cloned().collect(),
+        };
+        Some((data, updated_at))
+    }
+
+    /// When we last heard from the server. `None` if we haven't gotten the
+    /// initial set yet, or the connection is down
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.state.read().ok()?.updated_at
+    }
+
+    /// Are we connected, and have we heard from the server within `max_age`?
+    /// If not, the data can't be trusted to be current
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        self.updated_at()
+            .is_some_and(|updated_at| !is_expired(updated_at, max_age))
+    }
+
+    /// Number of consecutive failed connections
+    pub fn failures(&self) -> u32 {
+        self.state
+            .read()
+            .map(|state| state.failures)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for PredictionStream {
+    fn drop(&mut self) {
+        // The thread will notice next time it gets an event or reconnects
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Open a connection and apply events until it closes
+fn connect(
+    transport: &dyn Transport,
+    url: &str,
+    headers: &[(String, String)],
+    state: &RwLock<StreamState>,
+    stop: &AtomicBool,
+    received: &mut bool,
+) -> anyhow::Result<()> {
+    info!("Connecting to prediction stream {url}");
+    let headers: Vec<(&str, &str)> = [("Accept", "text/event-stream")]
+        .into_iter()
+        .chain(
+            headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .collect();
+    let response = transport.get_stream(
+        url,
+        &headers,
+        PredictionStream::MAX_CONNECTION_AGE,
+    )?;
+    if !response.is_success() {
+        bail!("HTTP {}", response.status);
+    }
+
+    let reader = BufReader::new(response.body);
+    let mut parser = EventParser::default();
+    for line in reader.lines() {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let line = line.context("Error reading stream")?;
+        if line.starts_with(':') {
+            // Keep-alive. Nothing changed, but the data is still current
+            state.write().map_err(|err| anyhow!("{err}"))?.touch();
+        }
+        let Some(event) = parser.push_line(&line) else {
+            continue;
+        };
+        trace!("Received {} event", event.event);
+        let mut state = state.write().map_err(|err| anyhow!("{err}"))?;
+        state
+            .apply(&event)
+            .with_context(|| format!("Error applying {} event", event.event))?;
+        *received = true;
+    }
+    Ok(())
+}
+
+impl StreamState {
+    /// Drop everything once the connection ends. There's no telling what we
+    /// missed while disconnected, so polling takes over until the next
+    /// connection's `reset`
+    fn disconnect(&mut self, received: bool) {
+        self.predictions.clear();
+        self.included.clear();
+        self.updated_at = None;
+        // Any event means the connection worked, even if it eventually broke
+        self.failures = if received { 0 } else { self.failures + 1 };
+    }
+
+    /// Note that the server is still there
+    fn touch(&mut self) {
+        if self.updated_at.is_some() {
+            self.updated_at = Some(Utc::now());
+        }
+    }
+
+    fn apply(&mut self, event: &Event) -> anyhow::Result<()> {
+        if event.event != "reset" && self.updated_at.is_none() {
+            // Changes are relative to the full set, so they're meaningless
+            // until we have it
+            debug!("Ignoring {} event before reset", event.event);
+            return Ok(());
+        }
+        match event.event.as_str() {
+            "reset" => {
+                let resources: Vec<Resource> =
+                    serde_json::from_str(&event.data)?;
+                self.predictions.clear();
+                self.included.clear();
+                for resource in resources {
+                    self.insert(resource)?;
+                }
+            }
+            "add" | "update" => {
+                self.insert(serde_json::from_str(&event.data)?)?;
+            }
+            "remove" => {
+                let resource: Resource = serde_json::from_str(&event.data)?;
+                if resource.kind == "prediction" {
+                    self.predictions.remove(&resource.id);
+                } else {
+                    self.included.remove(&(resource.kind, resource.id));
+                }
+            }
+            // Not worth failing over
+            other => debug!("Ignoring unknown event type {other}"),
+        }
+        self.updated_at = Some(Utc::now());
+        Ok(())
+    }
+
+    fn insert(&mut self, resource: Resource) -> anyhow::Result<()> {
+        if resource.kind == "prediction" {
+            let prediction = serde_json::from_value(resource.body)?;
+            self.predictions.insert(resource.id, prediction);
+        } else {
+            let included = serde_json::from_value(resource.body)?;
+            self.included.insert((resource.kind, resource.id), included);
+        }
+        Ok(())
+    }
+}
+
+/// A JSON:API resource of any type. We only look at the type and ID until
+/// we know what to parse the rest as
+struct Resource {
+    kind: String,
+    id: String,
+    body: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for Resource {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Identifier {
+            #[serde(rename = "type")]
+            kind: String,
+            id: String,
+        }
+
+        let body = serde_json::Value::deserialize(deserializer)?;
+        let Identifier { kind, id } =
+            Identifier::deserialize(&body).map_err(serde::de::Error::custom)?;
+        Ok(Self { kind, id, body })
+    }
+}
+
+/// A single server-sent event
+#[derive(Debug, PartialEq)]
+struct Event {
+    event: String,
+    data: String,
+}
+
+/// Incrementally parse server-sent events from lines of text.
+/// <https://html.spec.whatwg.org/multipage/server-sent-events.html>
+#[derive(Debug, Default)]
+struct EventParser {
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl EventParser {
+    /// Feed a line in. If it completes an event, return it
+    fn push_line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            // Blank line dispatches the event, if there is one
+            let event = self.event.take();
+            if self.data.is_empty() {
+                return None;
+            }
+            return Some(Event {
+                event: event.unwrap_or_else(|| "message".into()),
+                data: self.data.drain(..).collect::<Vec<_>>().join("\n"),
+            });
+        }
+        if line.starts_with(':') {
+            // Comment, used for keep-alives
+            return None;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => self.data.push(value.to_owned()),
+            // id and retry aren't useful to us
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::UreqTransport;
+    use itertools::Itertools;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        time::Instant,
+    };
+
+    fn prediction(id: &str, stop: &str, minute: u32) -> String {
+        format!(
+            r#"{{
+                "type": "prediction",
+                "id": "{id}",
+                "attributes": {{
+                    "arrival_time": null,
+                    "departure_time": "2024-05-24T13:{minute:02}:00-04:00",
+                    "direction_id": 1
+                }},
+                "relationships": {{
+                    "route": {{"data": {{"id": "87", "type": "route"}}}},
+                    "stop": {{"data": {{"id": "{stop}", "type": "stop"}}}},
+                    "trip": {{"data": {{"id": "t1", "type": "trip"}}}}
+                }}
+            }}"#
+        )
+        .replace('\n', "")
+    }
+
+    /// Serve a single SSE response with the given body on a local port, and
+    /// hand back the request it got. The connection is held open until the
+    /// returned sender is dropped, like a real stream would be
+    fn serve(
+        body: String,
+    ) -> (String, mpsc::Receiver<String>, mpsc::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            format!("http://{}/predictions", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        let (done, done_receiver) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            sender.send(String::from_utf8(request).unwrap()).unwrap();
+            write!(
+                socket,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                Connection: close\r\n\r\n{body}"
+            )
+            .unwrap();
+            // Returns an error once the test is done with us
+            let _ = done_receiver.recv();
+        });
+        (url, receiver, done)
+    }
+
+    #[test]
+    fn test_event_parser() {
+        let mut parser = EventParser::default();
+        let lines = [
+            ": keep-alive",
+            "",
+            "event: add",
+            "data: {\"a\":",
+            "data: 1}",
+            "",
+            "data:no space",
+            "",
+        ];
+        let events: Vec<_> = lines
+            .iter()
+            .filter_map(|line| parser.push_line(line))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                Event {
+                    event: "add".into(),
+                    data: "{\"a\":\n1}".into()
+                },
+                Event {
+                    event: "message".into(),
+                    data: "no space".into()
+                },
+            ]
+        );
+    }
+
+    /// Events are applied to the prediction set as they arrive
+    #[test]
+    fn test_stream() {
+        let body = format!(
+            "event: reset\ndata: [{}, {}]\n\n\
+            : keep-alive\n\n\
+            event: update\ndata: {}\n\n\
+            event: remove\ndata: {{\"type\": \"prediction\", \"id\": \"p2\"}}\n\n\
+            event: add\ndata: {}\n\n",
+            prediction("p1", "2545", 9),
+            prediction("p2", "2545", 10),
+            prediction("p1", "2570", 11),
+            prediction("p3", "2545", 12),
+        );
+        // Held until the end of the test, so the connection stays open
+        let (url, request, _done) = serve(body);
+        let stream = PredictionStream::start(
+            url,
+            vec![("x-api-key".into(), "hunter2".into())],
+            Arc::new(UreqTransport::default()),
+        );
+
+        let request = request
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .to_lowercase();
+        assert!(
+            request.contains("accept: text/event-stream\r\n"),
+            "{request}"
+        );
+        assert!(request.contains("x-api-key: hunter2\r\n"), "{request}");
+
+        // Wait for the last event to land
+        let expected = vec![
+            ("2545".to_owned(), "2024-05-24T17:12:00Z".parse().unwrap()),
+            ("2570".to_owned(), "2024-05-24T17:11:00Z".parse().unwrap()),
+        ];
+        let start = Instant::now();
+        loop {
+            let departures: Vec<(String, DateTime<Utc>)> = stream
+                .data()
+                .into_iter()
+                .flat_map(|(data, _)| data.data)
+                .map(|prediction| {
+                    (
+                        prediction.relationships.stop.data.id,
+                        prediction.attributes.departure_time.unwrap(),
+                    )
+                })
+                .sorted()
+                .collect();
+            if departures == expected {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for events; last state: {departures:?}"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(stream.failures(), 0);
+    }
+
+    /// Changes that arrive before the full set are ignored, so a partial set
+    /// isn't passed off as current
+    #[test]
+    fn test_before_reset() {
+        let mut state = StreamState::default();
+        state
+            .apply(&Event {
+                event: "add".into(),
+                data: prediction("p1", "2545", 9),
+            })
+            .unwrap();
+        assert!(state.predictions.is_empty());
+        assert_eq!(state.updated_at, None);
+
+        state
+            .apply(&Event {
+                event: "reset".into(),
+                data: format!("[{}]", prediction("p2", "2545", 10)),
+            })
+            .unwrap();
+        state
+            .apply(&Event {
+                event: "add".into(),
+                data: prediction("p1", "2545", 9),
+            })
+            .unwrap();
+        assert_eq!(
+            state.predictions.keys().sorted().collect::<Vec<_>>(),
+            ["p1", "p2"]
+        );
+        assert!(state.updated_at.is_some());
+    }
+
+    /// Everything is dropped when the connection ends, so stale predictions
+    /// don't stick around
+    #[test]
+    fn test_disconnect() {
+        let mut state = StreamState::default();
+        state
+            .apply(&Event {
+                event: "reset".into(),
+                data: format!("[{}]", prediction("p1", "2545", 9)),
+            })
+            .unwrap();
+        assert_eq!(state.predictions.len(), 1);
+        assert!(state.updated_at.is_some());
+
+        state.disconnect(true);
+        assert!(state.predictions.is_empty());
+        assert_eq!(state.updated_at, None);
+        assert_eq!(state.failures, 0);
+        // A keep-alive doesn't count as data
+        state.touch();
+        assert_eq!(state.updated_at, None);
+
+        state.disconnect(false);
+        assert_eq!(state.failures, 1);
+    }
+}
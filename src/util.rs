@@ -1,8 +1,16 @@
-use anyhow::{Context, anyhow};
-use log::{error, info, warn};
-use serde::de::DeserializeOwned;
+use crate::http::{Response, Transport};
+use anyhow::{Context, anyhow, bail};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone as _, Utc};
+use chrono_tz::Tz;
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::{
-    sync::{Arc, RwLock},
+    fmt::{self, Display},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
     thread,
     time::{Duration, Instant},
 };
@@ -11,28 +19,191 @@ use std::{
 #[derive(Debug)]
 pub struct ApiFetcher<T> {
     url: String,
+    /// How requests are actually made. Real HTTP in production, stubbed in
+    /// tests
+    transport: Arc<dyn Transport>,
     /// Frequency at which to refetch data
     ttl: Duration,
+    /// How to back off after failures
+    retry_policy: RetryPolicy,
+    /// Extra headers to send with every request
+    headers: Vec<(String, String)>,
+    /// Extra query parameters that depend on when the request is made
+    query: Option<Query>,
+    /// Decode a response body
+    parse: Parser<T>,
+    /// Where to persist the last good response, if anywhere
+    cache: Option<Cache>,
+    /// Cache validators from the last good response, to make conditional
+    /// requests
+    validators: Arc<Mutex<Validators>>,
     /// Data loaded from the API. The load is done in a separate thread and
     /// deposited here
-    data: Arc<RwLock<Option<(T, Instant)>>>,
+    data: DataSlot<T>,
+    /// Bookkeeping for in-flight and failed fetches
+    state: Arc<Mutex<FetchState>>,
 }
 
 impl<T> ApiFetcher<T>
 where
     T: 'static + Clone + DeserializeOwned + Send + Sync,
 {
-    pub fn new(url: String, ttl: Duration) -> Self {
+    /// Fetch JSON data
+    pub fn new(
+        url: String,
+        ttl: Duration,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        Self::new_with_parser(url, ttl, transport, |body| {
+            Ok(serde_json::from_slice(body)?)
+        })
+    }
+}
+
+impl<T> ApiFetcher<T>
+where
+    T: 'static + Clone + Send + Sync,
+{
+    /// If a fetch has been running this long, assume it's hung and allow
+    /// another one. The transport should time out well before this
+    const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Fetch data in some format other than JSON, decoded by `parse`
+    pub fn new_with_parser(
+        url: String,
+        ttl: Duration,
+        transport: Arc<dyn Transport>,
+        parse: Parser<T>,
+    ) -> Self {
         Self {
             url,
+            transport,
             ttl,
+            retry_policy: RetryPolicy::default(),
+            headers: Vec::new(),
+            query: None,
+            parse,
+            cache: None,
+            validators: Default::default(),
             data: Default::default(),
+            state: Default::default(),
+        }
+    }
+
+    /// Override the default retry policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Send a header with every request
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_owned(), value.into()));
+        self
+    }
+
+    /// Append query parameters to the URL for each request, based on the
+    /// current time. The cache is still keyed on the base URL, since the
+    /// query changes from one request to the next
+    pub fn with_query(mut self, query: Query) -> Self {
+        self.query = Some(query);
+        self
+    }
+
+    /// Persist each successful response to `{dir}/{name}.*`, and restore the
+    /// last one now so there's something to show before the first fetch
+    /// lands. The restored data keeps its original fetch time, so it will be
+    /// refetched right away if it's stale.
+    pub fn with_cache(mut self, dir: Option<&Path>, name: &str) -> Self {
+        let Some(dir) = dir else {
+            return self;
+        };
+        let cache = Cache {
+            dir: dir.to_owned(),
+            name: name.to_owned(),
+            saved: Default::default(),
+        };
+        match cache.load() {
+            Ok(Some((body, meta))) if meta.url == self.url => {
+                match (self.parse)(&body) {
+                    Ok(data) => {
+                        info!(
+                            "Restored cached data from {:?} (fetched at {})",
+                            cache.body_path(),
+                            meta.fetched_at
+                        );
+                        cache.saved(&body, &meta);
+                        // Nobody else has a handle on the locks yet
+                        if let Ok(mut guard) = self.data.write() {
+                            *guard = Some((data, meta.fetched_at));
+                        }
+                        if let Ok(mut guard) = self.validators.lock() {
+                            *guard = meta.validators;
+                        }
+                    }
+                    Err(err) => warn!(
+                        "Error parsing cached data from {:?}: {err:#}",
+                        cache.body_path()
+                    ),
+                }
+            }
+            // Cache is for a different request; it'll be overwritten soon
+            Ok(Some(_)) => {}
+            Ok(None) => {}
+            Err(err) => warn!("Error loading cache {name}: {err:#}"),
         }
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Is a background fetch currently running?
+    pub fn is_fetching(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => state
+                .in_flight
+                .is_some_and(|started| started.elapsed() < Self::FETCH_TIMEOUT),
+            Err(_) => false,
+        }
+    }
+
+    /// Number of consecutive failed fetches. Resets to 0 on success
+    pub fn failures(&self) -> u32 {
+        self.state
+            .lock()
+            .map(|state| state.failures)
+            .unwrap_or_default()
+    }
+
+    /// When was the current data fetched? `None` if there is no data
+    pub fn fetched_at(&self) -> Option<DateTime<Utc>> {
+        let guard = self.data.try_read().ok()?;
+        guard.as_ref().map(|(_, fetched_at)| *fetched_at)
+    }
+
+    /// Is the current data older than the TTL? This is the case after
+    /// restoring old data from the cache, or if fetches are failing
+    pub fn is_stale(&self) -> bool {
+        self.fetched_at()
+            .is_some_and(|fetched_at| is_expired(fetched_at, self.ttl))
     }
 
-    /// Get the latest data. If the data is missing or outdated, spawn a task to
-    /// re-fetch it
-    pub fn data(&self) -> Option<T> {
+    /// Get the latest data, and when it was fetched. If the data is missing or
+    /// outdated, spawn a task to re-fetch it
+    pub fn data(&self) -> Option<(T, DateTime<Utc>)> {
+        // Clone so we can release the lock
+        self.with_data(|data, fetched_at| (data.clone(), fetched_at))
+    }
+
+    /// Like [Self::data], but borrow the data instead of cloning it. The read
+    /// lock is held while `f` runs, so keep it short
+    pub fn with_data<R>(
+        &self,
+        f: impl FnOnce(&T, DateTime<Utc>) -> R,
+    ) -> Option<R> {
         let Some(guard) = self.data.try_read().ok() else {
             // Content is so low that we don't ever expect to hit this
             warn!("Failed to grab data read lock");
@@ -41,46 +212,930 @@ where
 
         if let Some((data, fetched_at)) = guard.as_ref() {
             // If forecast is stale, fetch a new one in the background
-            if *fetched_at + self.ttl < Instant::now() {
+            if is_expired(*fetched_at, self.ttl) {
                 self.fetch_latest();
             }
 
-            // Return the data even if it's old. Old is better than nothing.
-            // Clone so we can release the lock
-            Some(data.clone())
+            // Return the data even if it's old. Old is better than nothing,
+            // and the caller can decide what to do with it based on the age
+            Some(f(data, *fetched_at))
         } else {
             self.fetch_latest();
             None
         }
     }
 
-    /// Spawn a task to fetch the latest forecase in the background
+    /// Get the latest data without fetching, even if it's missing or outdated
+    pub fn peek(&self) -> Option<(T, DateTime<Utc>)> {
+        self.data.try_read().ok()?.clone()
+    }
+
+    /// Fetch the latest data on the current thread, blocking until it's
+    /// loaded. This ignores any backoff from previous failures
+    pub fn fetch_blocking(&self) -> anyhow::Result<()> {
+        let job = self.job();
+        let result = job.run();
+        if let Ok(mut state) = self.state.lock() {
+            state.finish(&result, &self.retry_policy);
+        }
+        result
+    }
+
+    /// Load data from a local file instead of the API, as if it had been
+    /// fetched at the given time. Useful for rendering against recorded
+    /// fixtures. The data won't be refetched in the background, no matter how
+    /// old it is
+    pub fn load_file(
+        &self,
+        path: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        info!("Loading data from {path:?}");
+        let body = fs::read(path)
+            .with_context(|| format!("Error opening {path:?}"))?;
+        let data = (self.parse)(&body)
+            .with_context(|| format!("Error parsing data from {path:?}"))?;
+        store(&self.data, data, fetched_at)?;
+        self.state.lock().map_err(|err| anyhow!("{err}"))?.pinned = true;
+        Ok(())
+    }
+
+    /// Spawn a task to fetch the latest forecase in the background, unless
+    /// one is already running or we're backing off after a failure
     fn fetch_latest(&self) {
-        let lock = Arc::clone(&self.data);
-        let url = self.url.clone();
-        let request = ureq::get(&url);
+        let started = Instant::now();
+        {
+            let Ok(mut state) = self.state.lock() else {
+                warn!("Failed to grab fetch state lock");
+                return;
+            };
+            if state.pinned {
+                return;
+            }
+            match state.in_flight {
+                Some(previous) if started - previous < Self::FETCH_TIMEOUT => {
+                    trace!("Fetch from {} already in flight", self.url);
+                    return;
+                }
+                Some(_) => {
+                    warn!("Fetch from {} timed out, retrying", self.url)
+                }
+                None => {}
+            }
+            if let Some(retry_at) = state.retry_at
+                && started < retry_at
+            {
+                trace!("Backing off from {} after failure", self.url);
+                return;
+            }
+            state.in_flight = Some(started);
+        }
+
+        let job = self.job();
+        let state = Arc::clone(&self.state);
+        let retry_policy = self.retry_policy.clone();
 
         thread::spawn(move || {
-            // Shitty try block
-            let result: anyhow::Result<()> = (|| {
-                info!("Fetching new data from {url}");
-                let mut response = request.call().with_context(|| {
-                    format!("Error fetching data from {url}")
+            let result = job.run();
+
+            let Ok(mut state) = state.lock() else {
+                error!("Failed to grab fetch state lock");
+                return;
+            };
+            let delay = state.finish(&result, &retry_policy);
+            if let Err(err) = &result {
+                error!(
+                    "Error fetching data from {} ({} consecutive failures, \
+                    retrying in {delay:?}): {err:?}",
+                    job.url, state.failures
+                );
+            }
+
+            // Only clear the flag if it's still ours. If this fetch timed out,
+            // another one may have started since
+            if state.in_flight == Some(started) {
+                state.in_flight = None;
+            }
+        });
+    }
+
+    /// Clone off everything a fetch needs, so it can run on another thread
+    fn job(&self) -> FetchJob<T> {
+        FetchJob {
+            url: self.url.clone(),
+            transport: Arc::clone(&self.transport),
+            headers: self.headers.clone(),
+            query: self.query,
+            parse: self.parse,
+            cache: self.cache.clone(),
+            validators: Arc::clone(&self.validators),
+            data: Arc::clone(&self.data),
+        }
+    }
+}
+
+/// Shared slot for fetched data, and when it was fetched
+type DataSlot<T> = Arc<RwLock<Option<(T, DateTime<Utc>)>>>;
+
+/// Decodes a response body into data
+pub type Parser<T> = fn(&[u8]) -> anyhow::Result<T>;
+
+/// Builds query parameters (starting with `&`) for a request made at the
+/// given time
+pub type Query = fn(DateTime<Utc>) -> String;
+
+/// A single fetch, detached from the [ApiFetcher] so it can run on a
+/// background thread
+struct FetchJob<T> {
+    url: String,
+    transport: Arc<dyn Transport>,
+    headers: Vec<(String, String)>,
+    query: Option<Query>,
+    parse: Parser<T>,
+    cache: Option<Cache>,
+    validators: Arc<Mutex<Validators>>,
+    data: DataSlot<T>,
+}
+
+impl<T> FetchJob<T> {
+    /// Fetch, parse, and store the data, and write it to the cache. If the
+    /// server says the data hasn't changed since the last fetch, just bump
+    /// the fetch time
+    fn run(&self) -> anyhow::Result<()> {
+        let validators = self
+            .validators
+            .lock()
+            .map_err(|err| anyhow!("{err}"))?
+            .clone();
+        let headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain(validators.headers())
+            .collect();
+        let fetched_at = Utc::now();
+        let url = match self.query {
+            Some(query) => format!("{}{}", self.url, query(fetched_at)),
+            None => self.url.clone(),
+        };
+        let body = match fetch(&*self.transport, &url, &headers)? {
+            Fetched::Modified(response) => {
+                let data = (self.parse)(&response.body).with_context(|| {
+                    format!("Error parsing data from {}", self.url)
                 })?;
-                let data: T =
-                    response.body_mut().read_json().with_context(|| {
-                        format!("Error parsing data from {url} as JSON")
-                    })?;
-
-                // Stringify the error to dump the lifetime
-                *lock.write().map_err(|err| anyhow!("{err}"))? =
-                    Some((data, Instant::now()));
-                Ok(())
-            })();
-
-            if let Err(err) = result {
-                error!("Error fetching data from {url}: {err:?}")
+                store(&self.data, data, fetched_at)?;
+                *self.validators.lock().map_err(|err| anyhow!("{err}"))? =
+                    Validators::from_response(&response);
+                Some(response.body)
+            }
+            Fetched::NotModified => {
+                let mut guard =
+                    self.data.write().map_err(|err| anyhow!("{err}"))?;
+                let Some((_, previous)) = guard.as_mut() else {
+                    // We only send validators when we have data, so the
+                    // server is confused
+                    bail!(
+                        "Data from {} not modified, but we have none",
+                        self.url
+                    );
+                };
+                *previous = fetched_at;
+                debug!("Data from {} not modified", self.url);
+                None
             }
+        };
+
+        // A cache failure shouldn't fail the fetch, since we have the data
+        if let Some(cache) = &self.cache {
+            let meta = CacheMeta {
+                url: self.url.clone(),
+                fetched_at,
+                validators: self
+                    .validators
+                    .lock()
+                    .map(|validators| validators.clone())
+                    .unwrap_or_default(),
+            };
+            if let Err(err) = cache.save(body.as_deref(), &meta) {
+                warn!("Error writing cache {}: {err:#}", cache.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Age thresholds for fetched data: past `stale_minutes` it should be flagged
+/// as old, and past `expire_minutes` it shouldn't be shown at all
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Staleness {
+    pub stale_minutes: i64,
+    pub expire_minutes: i64,
+}
+
+/// How old some data is, relative to its [Staleness] thresholds
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    Stale,
+    Expired,
+}
+
+impl Staleness {
+    pub fn check(
+        &self,
+        fetched_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Freshness {
+        let age = now - fetched_at;
+        if age > chrono::Duration::minutes(self.expire_minutes) {
+            Freshness::Expired
+        } else if age > chrono::Duration::minutes(self.stale_minutes) {
+            Freshness::Stale
+        } else {
+            Freshness::Fresh
+        }
+    }
+}
+
+/// Bookkeeping for fetches, shared with the background thread
+#[derive(Debug, Default)]
+struct FetchState {
+    /// When the current background fetch started, if there is one. Only one
+    /// fetch runs at a time, so a slow API doesn't pile up threads
+    in_flight: Option<Instant>,
+    /// Number of consecutive failed fetches. Reset on success
+    failures: u32,
+    /// After a failure, don't fetch again until this time
+    retry_at: Option<Instant>,
+    /// Data was loaded from a file, so never fetch over it
+    pinned: bool,
+}
+
+impl FetchState {
+    /// Record the outcome of a fetch. On failure, return how long until the
+    /// next attempt is allowed
+    fn finish(
+        &mut self,
+        result: &anyhow::Result<()>,
+        retry_policy: &RetryPolicy,
+    ) -> Duration {
+        match result {
+            Ok(()) => {
+                self.failures = 0;
+                self.retry_at = None;
+                Duration::ZERO
+            }
+            Err(err) => {
+                self.failures += 1;
+                let mut delay = retry_policy.delay(self.failures);
+                // If the server told us how long to wait, wait at least that
+                if let Some(retry_after) = err
+                    .downcast_ref::<HttpError>()
+                    .and_then(|err| err.retry_after)
+                {
+                    delay = delay.max(retry_after);
+                }
+                self.retry_at = Some(Instant::now() + delay);
+                delay
+            }
+        }
+    }
+}
+
+/// How to back off after failed fetches. The delay grows exponentially with
+/// each consecutive failure, up to a maximum, and resets after a success.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Delay after the first failure
+    pub initial_delay: Duration,
+    /// Upper bound on the delay, no matter how many failures
+    pub max_delay: Duration,
+    /// Multiply the delay by this for each additional consecutive failure
+    pub multiplier: f64,
+    /// Randomly shift each delay by up to this fraction in either direction,
+    /// so retries don't all land at once
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt, after `failures` consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let base = (self.initial_delay.as_secs_f64()
+            * self.multiplier.powi(exponent))
+        .min(self.max_delay.as_secs_f64());
+        let jitter = 1.0 + self.jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_secs_f64(
+            (base * jitter).clamp(0.0, self.max_delay.as_secs_f64()),
+        )
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(10 * 60),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// A response came back, but with a non-success status
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    /// Parsed from the `Retry-After` header, if present
+    retry_after: Option<Duration>,
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Outcome of a successful fetch
+enum Fetched {
+    Modified(Response),
+    /// 304 response to a conditional request; the data we have is current
+    NotModified,
+}
+
+/// Cache validators from a response, sent back in the next request so the
+/// server can skip the body if nothing changed
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &Response) -> Self {
+        Self {
+            etag: response.header("ETag").map(String::from),
+            last_modified: response.header("Last-Modified").map(String::from),
+        }
+    }
+
+    /// Headers to make a request conditional
+    fn headers(&self) -> Vec<(&str, &str)> {
+        let mut headers = Vec::new();
+        if let Some(etag) = &self.etag {
+            headers.push(("If-None-Match", etag.as_str()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push(("If-Modified-Since", last_modified.as_str()));
+        }
+        headers
+    }
+}
+
+/// Fetch a URL. The request is conditional if the headers include
+/// validators from a previous response
+fn fetch(
+    transport: &dyn Transport,
+    url: &str,
+    headers: &[(&str, &str)],
+) -> anyhow::Result<Fetched> {
+    info!("Fetching new data from {url}");
+    let response = transport
+        .get(url, headers)
+        .with_context(|| format!("Error fetching data from {url}"))?;
+    if response.status == 304 {
+        return Ok(Fetched::NotModified);
+    }
+    if !response.is_success() {
+        let error = HttpError {
+            status: response.status,
+            retry_after: response
+                .header("Retry-After")
+                .and_then(parse_retry_after),
+        };
+        return Err(anyhow::Error::new(error)
+            .context(format!("Error fetching data from {url}")));
+    }
+    Ok(Fetched::Modified(response))
+}
+
+/// Deposit freshly loaded data into the shared slot
+fn store<T>(
+    lock: &RwLock<Option<(T, DateTime<Utc>)>>,
+    data: T,
+    fetched_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    // Stringify the error to dump the lifetime
+    *lock.write().map_err(|err| anyhow!("{err}"))? = Some((data, fetched_at));
+    Ok(())
+}
+
+/// Has it been more than `ttl` since `fetched_at`?
+pub fn is_expired(fetched_at: DateTime<Utc>, ttl: Duration) -> bool {
+    Utc::now() - fetched_at
+        > chrono::Duration::from_std(ttl).unwrap_or_default()
+}
+
+/// On-disk copy of the last good response for a fetcher. The raw body is
+/// stored alongside a small metadata file.
+#[derive(Clone, Debug)]
+struct Cache {
+    dir: PathBuf,
+    name: String,
+    /// What's on disk now, so we can skip writes that wouldn't change it.
+    /// The cache usually lives on an SD card, which wears out
+    saved: Arc<Mutex<Option<SavedState>>>,
+}
+
+/// Summary of what's in the cache files
+#[derive(Debug)]
+struct SavedState {
+    body_hash: u64,
+    validators: Validators,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Metadata about a cached response
+#[derive(Debug, Deserialize, Serialize)]
+struct CacheMeta {
+    /// URL the response came from. If this doesn't match the current URL, the
+    /// cache is for an old config and should be ignored
+    url: String,
+    fetched_at: DateTime<Utc>,
+    #[serde(default)]
+    validators: Validators,
+}
+
+impl Cache {
+    /// If the data hasn't changed, only bump the fetch time on disk this
+    /// often. It only matters when restoring, to tell how stale the data is
+    const FETCHED_AT_INTERVAL: chrono::Duration = chrono::Duration::minutes(10);
+
+    fn body_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.body", self.name))
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.meta.json", self.name))
+    }
+
+    /// Load the cached response. `None` if nothing has been cached yet
+    fn load(&self) -> anyhow::Result<Option<(Vec<u8>, CacheMeta)>> {
+        let meta_path = self.meta_path();
+        let meta = match fs::read(&meta_path) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(err)
+                    .context(format!("Error reading {meta_path:?}"));
+            }
+        };
+        let meta: CacheMeta = serde_json::from_slice(&meta)
+            .with_context(|| format!("Error parsing {meta_path:?}"))?;
+        let body_path = self.body_path();
+        let body = fs::read(&body_path)
+            .with_context(|| format!("Error reading {body_path:?}"))?;
+        Ok(Some((body, meta)))
+    }
+
+    /// Store a response. The metadata is written last, so a partial write
+    /// is never mistaken for a complete one. If the body is `None`, it
+    /// hasn't changed and only the metadata is updated. Writes that wouldn't
+    /// change anything but the fetch time are skipped, unless the fetch time
+    /// on disk is getting old
+    fn save(
+        &self,
+        body: Option<&[u8]>,
+        meta: &CacheMeta,
+    ) -> anyhow::Result<()> {
+        let mut saved = self.saved.lock().map_err(|err| anyhow!("{err}"))?;
+        let body_hash = body.map(hash);
+        let body_changed = body_hash.is_some_and(|body_hash| {
+            saved
+                .as_ref()
+                .is_none_or(|saved| saved.body_hash != body_hash)
+        });
+        let meta_changed = saved.as_ref().is_none_or(|saved| {
+            saved.validators != meta.validators
+                || meta.fetched_at - saved.fetched_at
+                    >= Self::FETCHED_AT_INTERVAL
+        });
+        if !body_changed && !meta_changed {
+            trace!("Cache {} unchanged, skipping write", self.name);
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Error creating {:?}", self.dir))?;
+        if let Some(body) = body.filter(|_| body_changed) {
+            write_atomic(&self.body_path(), body)?;
+        }
+        write_atomic(&self.meta_path(), &serde_json::to_vec(meta)?)?;
+        *saved = Some(SavedState {
+            body_hash: body_hash
+                .or(saved.as_ref().map(|saved| saved.body_hash))
+                .unwrap_or_default(),
+            validators: meta.validators.clone(),
+            fetched_at: meta.fetched_at,
         });
+        Ok(())
+    }
+
+    /// Record what's on disk, after restoring it
+    fn saved(&self, body: &[u8], meta: &CacheMeta) {
+        if let Ok(mut saved) = self.saved.lock() {
+            *saved = Some(SavedState {
+                body_hash: hash(body),
+                validators: meta.validators.clone(),
+                fetched_at: meta.fetched_at,
+            });
+        }
+    }
+}
+
+/// Hash a response body, to tell if it changed
+fn hash(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Write to a temp file then move it into place, so a crash mid-write can't
+/// leave a truncated file behind
+fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)
+        .with_context(|| format!("Error writing {temp_path:?}"))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Error moving {temp_path:?} to {path:?}"))
+}
+
+/// Parse a `Retry-After` header, which is either a number of seconds or an
+/// HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can retry right away
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Status of an [ApiFetcher], for debugging
+#[derive(Clone, Debug, Serialize)]
+pub struct FetchStatus {
+    pub refreshing: bool,
+    pub failures: u32,
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// Is the data older than its TTL?
+    pub stale: bool,
+}
+
+impl<T> From<&ApiFetcher<T>> for FetchStatus
+where
+    T: 'static + Clone + Send + Sync,
+{
+    fn from(fetcher: &ApiFetcher<T>) -> Self {
+        Self {
+            refreshing: fetcher.is_fetching(),
+            failures: fetcher.failures(),
+            fetched_at: fetcher.fetched_at(),
+            stale: fetcher.is_stale(),
+        }
+    }
+}
+
+/// Timezone to show times in: either the system timezone, or a named one
+/// from the config, e.g. `America/New_York`
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(from = "Option<Tz>")]
+pub enum LocalTz {
+    #[default]
+    System,
+    Named(Tz),
+}
+
+impl LocalTz {
+    /// Convert a time to this timezone
+    pub fn localize(self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Self::System => time.with_timezone(&Local).fixed_offset(),
+            Self::Named(tz) => time.with_timezone(&tz).fixed_offset(),
+        }
+    }
+
+    /// Get the moment a wall clock time happens in this timezone. `None` if
+    /// the clocks skip over it or pass it twice
+    pub fn to_utc(self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let time = match self {
+            Self::System => Local.from_local_datetime(&time).single()?.to_utc(),
+            Self::Named(tz) => tz.from_local_datetime(&time).single()?.to_utc(),
+        };
+        Some(time)
+    }
+}
+
+impl From<Option<Tz>> for LocalTz {
+    fn from(tz: Option<Tz>) -> Self {
+        tz.map(Self::Named).unwrap_or_default()
+    }
+}
+
+/// When the fixtures were recorded, for tests to use as the current time
+#[cfg(test)]
+pub fn fixture_now() -> DateTime<Utc> {
+    "2024-05-24T13:05:00-04:00".parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Stub, StubTransport};
+    use std::env;
+
+    #[derive(Clone, Debug, PartialEq, Deserialize)]
+    struct Data {
+        value: u32,
+    }
+
+    const URL: &str = "https://example.com/data";
+    /// Long enough that data never goes stale during a test
+    const TTL: Duration = Duration::from_secs(60 * 60);
+
+    fn fetcher(stubs: impl IntoIterator<Item = Stub>) -> ApiFetcher<Data> {
+        ApiFetcher::new(URL.into(), TTL, Arc::new(StubTransport::new(stubs)))
+    }
+
+    /// Poll until a background fetch lands
+    fn wait_for_data(fetcher: &ApiFetcher<Data>) -> Data {
+        let start = Instant::now();
+        loop {
+            if let Some((data, _)) = fetcher.data() {
+                return data;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Timed out waiting for data"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// First access kicks off a fetch in the background
+    #[test]
+    fn test_background_fetch() {
+        let fetcher = fetcher([
+            Stub::ok(r#"{"value": 1}"#).latency(Duration::from_millis(50))
+        ]);
+        assert_eq!(fetcher.data(), None);
+        assert_eq!(wait_for_data(&fetcher), Data { value: 1 });
+    }
+
+    /// Repeated access while a fetch is running doesn't start another one
+    #[test]
+    fn test_single_fetch_in_flight() {
+        let transport = StubTransport::new([
+            Stub::ok(r#"{"value": 1}"#).latency(Duration::from_millis(100))
+        ]);
+        let fetcher: ApiFetcher<Data> =
+            ApiFetcher::new(URL.into(), TTL, Arc::new(transport.clone()));
+        assert!(!fetcher.is_fetching());
+        for _ in 0..5 {
+            assert_eq!(fetcher.data(), None);
+        }
+        assert!(fetcher.is_fetching());
+        assert_eq!(wait_for_data(&fetcher), Data { value: 1 });
+        assert_eq!(transport.requests(), [URL]);
+        assert!(!fetcher.is_fetching());
+    }
+
+    /// Failures back off exponentially, and a success resets the policy
+    #[test]
+    fn test_backoff() {
+        let transport = StubTransport::new([
+            Stub::status(500, "oops"),
+            Stub::status(500, "oops"),
+            Stub::ok(r#"{"value": 1}"#),
+        ]);
+        let fetcher: ApiFetcher<Data> =
+            ApiFetcher::new(URL.into(), TTL, Arc::new(transport.clone()))
+                .with_retry_policy(RetryPolicy {
+                    initial_delay: Duration::from_millis(100),
+                    max_delay: Duration::from_secs(60),
+                    multiplier: 10.0,
+                    jitter: 0.0,
+                });
+
+        assert!(fetcher.fetch_blocking().is_err());
+        assert_eq!(fetcher.failures(), 1);
+        // We're backing off, so this shouldn't trigger a fetch
+        assert_eq!(fetcher.data(), None);
+        assert_eq!(transport.requests().len(), 1);
+
+        // Wait out the backoff. The next failure backs off for 1s
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(fetcher.data(), None);
+        while fetcher.is_fetching() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fetcher.failures(), 2);
+        assert_eq!(fetcher.data(), None);
+        assert_eq!(transport.requests().len(), 2);
+
+        // An explicit fetch ignores backoff, and success resets the count
+        fetcher.fetch_blocking().unwrap();
+        assert_eq!(fetcher.failures(), 0);
+        assert_eq!(
+            fetcher.data().map(|(data, _)| data),
+            Some(Data { value: 1 })
+        );
+    }
+
+    /// Retry-After extends the backoff beyond what the policy says
+    #[test]
+    fn test_retry_after() {
+        let fetcher =
+            fetcher([Stub::status(429, "").header("Retry-After", "120")]);
+        assert!(fetcher.fetch_blocking().is_err());
+        let retry_at = fetcher.state.lock().unwrap().retry_at.unwrap();
+        assert!(retry_at - Instant::now() > Duration::from_secs(110));
+
+        assert_eq!(parse_retry_after(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+        let delays: Vec<u64> = (1..=6)
+            .map(|failures| policy.delay(failures).as_secs())
+            .collect();
+        assert_eq!(delays, [5, 10, 20, 40, 60, 60]);
+
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!((5..=15).contains(&delay.as_secs()), "{delay:?}");
+        }
+    }
+
+    /// A failed fetch of any kind keeps the old data around
+    #[test]
+    fn test_keep_stale_data() {
+        for (failure, expected_error) in [
+            (Stub::status(500, "oops"), "HTTP 500"),
+            (Stub::ok("not json"), "Error parsing data"),
+            (Stub::error("Connection refused"), "Connection refused"),
+        ] {
+            let fetcher = fetcher([Stub::ok(r#"{"value": 1}"#), failure]);
+            fetcher.fetch_blocking().unwrap();
+            let error = fetcher.fetch_blocking().unwrap_err();
+            assert!(
+                format!("{error:#}").contains(expected_error),
+                "Expected error to contain {expected_error:?}, got {error:#}"
+            );
+            assert_eq!(
+                fetcher.data().map(|(data, _)| data),
+                Some(Data { value: 1 })
+            );
+        }
+    }
+
+    /// A successful fetch is written to the cache, and restored by the next
+    /// fetcher to use it. A cache from a different URL is ignored
+    #[test]
+    fn test_cache() {
+        let dir = env::temp_dir()
+            .join(format!("goldfinger-test-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let original = fetcher([Stub::ok(r#"{"value": 1}"#)])
+            .with_cache(Some(&dir), "data");
+        assert_eq!(original.fetched_at(), None);
+        original.fetch_blocking().unwrap();
+        let fetched_at = original.fetched_at().unwrap();
+
+        // Restored data keeps its original fetch time. Use a failing
+        // transport so a refetch can't mask the restore
+        let restored =
+            fetcher([Stub::error("Offline")]).with_cache(Some(&dir), "data");
+        assert_eq!(restored.fetched_at(), Some(fetched_at));
+        assert_eq!(
+            restored.data().map(|(data, _)| data),
+            Some(Data { value: 1 })
+        );
+
+        let other: ApiFetcher<Data> = ApiFetcher::new(
+            "https://example.com/other".into(),
+            TTL,
+            Arc::new(StubTransport::new([Stub::error("Offline")])),
+        )
+        .with_cache(Some(&dir), "data");
+        assert_eq!(other.fetched_at(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The cache is only rewritten when the response changes
+    #[test]
+    fn test_cache_unchanged() {
+        let dir = env::temp_dir().join(format!(
+            "goldfinger-test-cache-unchanged-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let fetcher = fetcher([
+            Stub::ok(r#"{"value": 1}"#).header("ETag", "\"a\""),
+            Stub::status(304, ""),
+            Stub::ok(r#"{"value": 1}"#).header("ETag", "\"a\""),
+            Stub::ok(r#"{"value": 2}"#).header("ETag", "\"b\""),
+        ])
+        .with_cache(Some(&dir), "data");
+        let cache = fetcher.cache.clone().unwrap();
+        let cached_at = || cache.load().unwrap().unwrap().1.fetched_at;
+
+        fetcher.fetch_blocking().unwrap();
+        let fetched_at = fetcher.fetched_at().unwrap();
+        assert_eq!(cached_at(), fetched_at);
+
+        // Not modified, then the same body again
+        fetcher.fetch_blocking().unwrap();
+        fetcher.fetch_blocking().unwrap();
+        assert_eq!(cached_at(), fetched_at);
+
+        fetcher.fetch_blocking().unwrap();
+        assert_eq!(cached_at(), fetcher.fetched_at().unwrap());
+        assert_eq!(cache.load().unwrap().unwrap().0, br#"{"value": 2}"#);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Validators from the last response are sent back, and a 304 bumps the
+    /// fetch time without touching the data
+    #[test]
+    fn test_conditional_request() {
+        let transport = StubTransport::new([
+            Stub::ok(r#"{"value": 1}"#)
+                .header("ETag", "\"abc\"")
+                .header("Last-Modified", "Fri, 24 May 2024 17:00:00 GMT"),
+            Stub::status(304, ""),
+        ]);
+        let fetcher: ApiFetcher<Data> =
+            ApiFetcher::new(URL.into(), TTL, Arc::new(transport.clone()));
+
+        fetcher.fetch_blocking().unwrap();
+        // Backdate the first fetch, so the bump doesn't depend on the clock
+        // ticking between fetches
+        let fetched_at = Utc::now() - chrono::Duration::minutes(1);
+        store(&fetcher.data, Data { value: 1 }, fetched_at).unwrap();
+
+        let before = Utc::now();
+        fetcher.fetch_blocking().unwrap();
+        let refetched_at = fetcher.fetched_at().unwrap();
+        assert!(refetched_at >= before);
+        assert!(refetched_at > fetched_at);
+        // The 304 kept the data and the validators
+        assert_eq!(
+            fetcher.data().map(|(data, _)| data),
+            Some(Data { value: 1 })
+        );
+        assert_eq!(
+            fetcher.validators.lock().unwrap().etag.as_deref(),
+            Some("\"abc\"")
+        );
+
+        let headers = transport.request_headers();
+        assert_eq!(headers[0], []);
+        assert_eq!(
+            headers[1],
+            [
+                ("If-None-Match".to_owned(), "\"abc\"".to_owned()),
+                (
+                    "If-Modified-Since".to_owned(),
+                    "Fri, 24 May 2024 17:00:00 GMT".to_owned()
+                ),
+            ]
+        );
     }
 }
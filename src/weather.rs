@@ -1,16 +1,76 @@
-use crate::{config::Config, util::ApiFetcher};
-use chrono::{DateTime, Local, NaiveTime, Utc};
-use serde::Deserialize;
-use std::time::Duration;
+mod alerts;
+mod daily;
+
+use self::{
+    alerts::Alerts,
+    daily::{DailyForecast, DaySummary},
+};
+use crate::{
+    config::Config,
+    http::Transport,
+    util::{ApiFetcher, FetchStatus, LocalTz, RetryPolicy},
+};
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeDelta, Utc};
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 /// Gotta know weather or not it's gonna rain
 #[derive(Debug)]
 pub struct Weather {
-    fetcher: ApiFetcher<Forecast>,
+    /// Where the forecast URLs come from
+    location: Location,
+    forecast: Deferred<Forecast>,
+    /// 12-hour day/night periods, for daily highs and lows
+    daily: Deferred<DailyForecast>,
+    /// Stations near the location, nearest first. Unused if the config picks
+    /// a station
+    stations: Deferred<Stations>,
+    /// Latest observed conditions from the station
+    observation: Deferred<Observation>,
+    /// Observation station from the config
+    observation_station: Option<String>,
+    /// Active watches and warnings. Alerts are looked up by latitude and
+    /// longitude, so this is `None` if those aren't configured
+    alerts: Option<ApiFetcher<Alerts>>,
+    /// Everything needed to build fetchers once we know their URLs
+    transport: Arc<dyn Transport>,
+    user_agent: String,
+    cache_dir: Option<PathBuf>,
+    /// For deciding which day it is
+    timezone: LocalTz,
 }
 
 impl Weather {
     const FORECAST_TTL: Duration = Duration::from_secs(60);
+    /// Daily periods change slowly, and we only show a summary of them
+    const DAILY_TTL: Duration = Duration::from_secs(10 * 60);
+    /// NWS occasionally moves grid boundaries, so re-resolve the location
+    /// every so often
+    const POINTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Stations don't come and go often either
+    const STATIONS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Most stations report hourly, some more often
+    const OBSERVATION_TTL: Duration = Duration::from_secs(5 * 60);
+    /// Past this, the forecast is a better guess at what's happening outside
+    /// than the last observation
+    const MAX_OBSERVATION_AGE: TimeDelta = TimeDelta::hours(1);
+    /// Alerts can be issued at any time, so check often
+    const ALERTS_TTL: Duration = Duration::from_secs(2 * 60);
+    /// NWS asks clients not to hammer the API, so back off generously
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        initial_delay: Duration::from_secs(30),
+        max_delay: Duration::from_secs(15 * 60),
+        multiplier: 2.0,
+        jitter: 0.2,
+    };
     const API_HOST: &'static str = "https://api.weather.gov";
     // Start and end (inclusive) of forecast times that *should* be shown.
     // unstable: const unwrap https://github.com/rust-lang/rust/issues/67441
@@ -18,80 +78,558 @@ impl Weather {
     const DAY_END: NaiveTime = NaiveTime::from_hms_opt(22, 30, 0).unwrap();
     /// We show every n periods in the future
     const PERIOD_INTERNAL: usize = 4;
+    // Fixture files (within the fixture directory)
+    const POINTS_FIXTURE: &'static str = "points.json";
+    const STATIONS_FIXTURE: &'static str = "stations.json";
+    const FORECAST_FIXTURE: &'static str = "forecast_hourly.json";
+    const DAILY_FIXTURE: &'static str = "forecast.json";
+    const OBSERVATION_FIXTURE: &'static str = "observation.json";
+    const ALERTS_FIXTURE: &'static str = "weather_alerts.json";
+
+    pub fn new(
+        config: &Config,
+        transport: Arc<dyn Transport>,
+    ) -> anyhow::Result<Self> {
+        let user_agent = user_agent(config);
+        // An explicit gridpoint wins, so there's no need to look it up
+        let location = match (
+            &config.forecast_office,
+            config.forecast_gridpoint,
+            config.latitude.zip(config.longitude),
+        ) {
+            (Some(office), Some((x, y)), _) => {
+                Location::Fixed(PointProperties::gridpoint(office, x, y))
+            }
+            (_, _, Some((latitude, longitude))) => {
+                // NWS redirects anything more precise than this
+                let url = format!(
+                    "{}/points/{latitude:.4},{longitude:.4}",
+                    Self::API_HOST
+                );
+                Location::Resolved(
+                    ApiFetcher::new(
+                        url,
+                        Self::POINTS_TTL,
+                        Arc::clone(&transport),
+                    )
+                    .with_header("User-Agent", user_agent.as_str())
+                    .with_retry_policy(Self::RETRY_POLICY)
+                    .with_cache(config.cache_dir.as_deref(), "points"),
+                )
+            }
+            _ => bail!(
+                "Weather needs either latitude and longitude, or \
+                forecast_office and forecast_gridpoint"
+            ),
+        };
+        let mut weather = Self {
+            location,
+            forecast: Deferred::default(),
+            daily: Deferred::default(),
+            stations: Deferred::default(),
+            observation: Deferred::default(),
+            observation_station: config.observation_station.clone(),
+            alerts: None,
+            transport,
+            user_agent,
+            cache_dir: config.cache_dir.clone(),
+            timezone: config.timezone,
+        };
+        match config.latitude.zip(config.longitude) {
+            Some((latitude, longitude)) => {
+                let url = format!(
+                    "{}/alerts/active?point={latitude:.4},{longitude:.4}",
+                    Self::API_HOST
+                );
+                weather.alerts = Some(weather.fetcher(
+                    url,
+                    Self::ALERTS_TTL,
+                    "weather_alerts",
+                ));
+            }
+            None => warn!(
+                "No latitude and longitude configured; weather alerts are \
+                disabled"
+            ),
+        }
+        // If the location is known or cached, the cached forecast can be
+        // restored right away. Don't kick off a lookup yet though; that
+        // happens on the first tick
+        weather.resolve(false);
+        Ok(weather)
+    }
+
+    /// Get the latest forecast, and when it was fetched. If the forecast is
+    /// missing or outdated, spawn a task to re-fetch it
+    pub fn forecast(&self) -> Option<(Forecast, DateTime<Utc>)> {
+        self.resolve(true);
+        self.forecast.get(ApiFetcher::data).flatten()
+    }
+
+    /// Get high/low summaries for today and tomorrow. Empty if the daily
+    /// forecast hasn't been fetched yet. If it's missing or outdated, spawn a
+    /// task to re-fetch it
+    pub fn daily(&self, now: DateTime<Utc>) -> Vec<DaySummary> {
+        self.resolve(true);
+        self.daily
+            .get(ApiFetcher::data)
+            .flatten()
+            .map(|(daily, _)| daily.days(now, self.timezone))
+            .unwrap_or_default()
+    }
+
+    /// Get the latest observed conditions. `None` if there's no observation
+    /// yet, or it's too old to trust. If the observation is missing or
+    /// outdated, spawn a task to re-fetch it
+    pub fn observation(&self, now: DateTime<Utc>) -> Option<Observation> {
+        self.resolve(true);
+        let (observation, _) = self.observation.get(ApiFetcher::data)??;
+        (now - observation.properties.timestamp <= Self::MAX_OBSERVATION_AGE)
+            .then_some(observation)
+    }
 
-    pub fn new(config: &Config) -> Self {
+    /// Get a label for the most pressing watch or warning in effect, if any.
+    /// If the alerts are missing or outdated, spawn a task to re-fetch them
+    pub fn alert(&self, now: DateTime<Utc>) -> Option<String> {
+        let (alerts, _) = self.alerts.as_ref()?.data()?;
+        alerts.label(now, self.timezone)
+    }
+
+    /// Is a new forecast being fetched right now?
+    pub fn is_refreshing(&self) -> bool {
+        let resolving = match &self.location {
+            Location::Fixed(_) => false,
+            Location::Resolved(points) => points.is_fetching(),
+        };
+        resolving || self.forecast.get(ApiFetcher::is_fetching) == Some(true)
+    }
+
+    /// Status of each fetcher, for debugging. Fetchers that are waiting on
+    /// the location to be resolved are left out
+    pub fn status(&self) -> BTreeMap<&'static str, FetchStatus> {
+        let mut status = BTreeMap::new();
+        if let Location::Resolved(points) = &self.location {
+            status.insert("points", points.into());
+        }
+        let deferred = [
+            ("stations", self.stations.get(|fetcher| fetcher.into())),
+            (
+                "forecast_hourly",
+                self.forecast.get(|fetcher| fetcher.into()),
+            ),
+            ("forecast", self.daily.get(|fetcher| fetcher.into())),
+            (
+                "observation",
+                self.observation.get(|fetcher| fetcher.into()),
+            ),
+        ];
+        for (name, fetcher) in deferred {
+            if let Some(fetcher) = fetcher {
+                status.insert(name, fetcher);
+            }
+        }
+        if let Some(alerts) = &self.alerts {
+            status.insert("alerts", alerts.into());
+        }
+        status
+    }
+
+    /// Fetch the forecasts, observation, and alerts immediately, blocking
+    /// until they're loaded
+    pub fn fetch_blocking(&self) -> anyhow::Result<()> {
+        if let Location::Resolved(points) = &self.location {
+            points.fetch_blocking()?;
+        }
+        self.resolve(false);
+        if self.observation_station.is_none() {
+            self.stations.fetch_blocking()?;
+            self.resolve(false);
+        }
+        self.forecast.fetch_blocking()?;
+        self.daily.fetch_blocking()?;
+        self.observation.fetch_blocking()?;
+        if let Some(alerts) = &self.alerts {
+            alerts.fetch_blocking()?;
+        }
+        Ok(())
+    }
+
+    /// Load recorded API responses from a fixture directory instead of the
+    /// API. Lookups that aren't needed for this config are skipped
+    pub fn load_fixtures(
+        &self,
+        dir: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        if let Location::Resolved(points) = &self.location {
+            points.load_file(&dir.join(Self::POINTS_FIXTURE), fetched_at)?;
+        }
+        self.resolve(false);
+        if self.observation_station.is_none() {
+            self.stations
+                .load_file(&dir.join(Self::STATIONS_FIXTURE), fetched_at)?;
+            self.resolve(false);
+        }
+        self.forecast
+            .load_file(&dir.join(Self::FORECAST_FIXTURE), fetched_at)?;
+        self.daily
+            .load_file(&dir.join(Self::DAILY_FIXTURE), fetched_at)?;
+        self.observation
+            .load_file(&dir.join(Self::OBSERVATION_FIXTURE), fetched_at)?;
+        if let Some(alerts) = &self.alerts {
+            alerts.load_file(&dir.join(Self::ALERTS_FIXTURE), fetched_at)?;
+        }
+        Ok(())
+    }
+
+    /// Point each fetcher at the URL for the current location, as far as it's
+    /// been resolved. With `refresh`, kick off lookups for anything missing
+    /// or outdated along the way
+    fn resolve(&self, refresh: bool) {
+        let Some(points) = self.location.points(refresh) else {
+            return;
+        };
+        self.forecast.point_to(&points.forecast_hourly, |url| {
+            info!(
+                "Using forecast for gridpoint {} {},{}",
+                points.grid_id, points.grid_x, points.grid_y
+            );
+            self.fetcher(url, Self::FORECAST_TTL, "forecast_hourly")
+        });
+        self.daily.point_to(&points.forecast, |url| {
+            self.fetcher(url, Self::DAILY_TTL, "forecast")
+        });
+
+        let station = match &self.observation_station {
+            Some(station) => station.clone(),
+            None => {
+                self.stations.point_to(&points.observation_stations, |url| {
+                    self.fetcher(url, Self::STATIONS_TTL, "stations")
+                });
+                let nearest = self
+                    .stations
+                    .get(|fetcher| latest(fetcher, refresh))
+                    .flatten()
+                    .and_then(|(stations, _)| stations.nearest());
+                let Some(station) = nearest else {
+                    return;
+                };
+                station
+            }
+        };
         let url = format!(
-            "{}/gridpoints/{}/{},{}/forecast/hourly",
-            Self::API_HOST,
-            config.forecast_office,
-            config.forecast_gridpoint.0,
-            config.forecast_gridpoint.1
+            "{}/stations/{station}/observations/latest",
+            Self::API_HOST
         );
+        self.observation.point_to(&url, |url| {
+            info!("Using observations from station {station}");
+            self.fetcher(url, Self::OBSERVATION_TTL, "observation")
+        });
+    }
+
+    /// Build a fetcher for an NWS endpoint
+    fn fetcher<T>(
+        &self,
+        url: String,
+        ttl: Duration,
+        cache_name: &str,
+    ) -> ApiFetcher<T>
+    where
+        T: 'static + Clone + DeserializeOwned + Send + Sync,
+    {
+        ApiFetcher::new(url, ttl, Arc::clone(&self.transport))
+            .with_header("User-Agent", self.user_agent.as_str())
+            .with_retry_policy(Self::RETRY_POLICY)
+            .with_cache(self.cache_dir.as_deref(), cache_name)
+    }
+}
+
+/// Where we are, as far as NWS is concerned
+#[derive(Debug)]
+enum Location {
+    /// Gridpoint given in the config
+    Fixed(PointProperties),
+    /// Looked up from latitude and longitude
+    Resolved(ApiFetcher<Points>),
+}
+
+impl Location {
+    /// Get the gridpoint and its URLs. `None` if it hasn't been looked up
+    /// yet. With `refresh`, look it up if it's missing or old
+    fn points(&self, refresh: bool) -> Option<PointProperties> {
+        match self {
+            Self::Fixed(properties) => Some(properties.clone()),
+            Self::Resolved(fetcher) => {
+                latest(fetcher, refresh).map(|(points, _)| points.properties)
+            }
+        }
+    }
+}
+
+/// Get a fetcher's data. With `refresh`, spawn a task to re-fetch it if it's
+/// missing or outdated
+fn latest<T>(
+    fetcher: &ApiFetcher<T>,
+    refresh: bool,
+) -> Option<(T, DateTime<Utc>)>
+where
+    T: 'static + Clone + Send + Sync,
+{
+    if refresh {
+        fetcher.data()
+    } else {
+        fetcher.peek()
+    }
+}
+
+/// An [ApiFetcher] whose URL depends on the location, so it can't be built
+/// until the location is resolved. It's rebuilt whenever the URL changes
+#[derive(Debug)]
+struct Deferred<T> {
+    fetcher: RwLock<Option<ApiFetcher<T>>>,
+}
+
+impl<T> Deferred<T>
+where
+    T: 'static + Clone + DeserializeOwned + Send + Sync,
+{
+    /// Make sure the fetcher is pointed at a URL, building a new one if not
+    fn point_to(&self, url: &str, build: impl FnOnce(String) -> ApiFetcher<T>) {
+        let is_current = |fetcher: &Option<ApiFetcher<T>>| {
+            fetcher.as_ref().is_some_and(|fetcher| fetcher.url() == url)
+        };
+        if self.fetcher.read().is_ok_and(|guard| is_current(&guard)) {
+            return;
+        }
+        match self.fetcher.write() {
+            Ok(mut guard) if !is_current(&guard) => {
+                *guard = Some(build(url.to_owned()));
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Error updating fetcher for {url}: {err}"),
+        }
+    }
+
+    /// Do something with the fetcher. `None` if it hasn't been built yet
+    fn get<R>(&self, f: impl FnOnce(&ApiFetcher<T>) -> R) -> Option<R> {
+        let guard = self.fetcher.read().ok()?;
+        guard.as_ref().map(f)
+    }
+
+    fn fetch_blocking(&self) -> anyhow::Result<()> {
+        self.get(ApiFetcher::fetch_blocking)
+            .ok_or_else(|| anyhow!("Location isn't resolved"))?
+    }
+
+    fn load_file(
+        &self,
+        path: &Path,
+        fetched_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.get(|fetcher| fetcher.load_file(path, fetched_at))
+            .ok_or_else(|| anyhow!("Location isn't resolved"))?
+    }
+}
+
+impl<T> Default for Deferred<T> {
+    fn default() -> Self {
         Self {
-            fetcher: ApiFetcher::new(url, Self::FORECAST_TTL),
+            fetcher: RwLock::new(None),
         }
     }
+}
+
+/// <https://www.weather.gov/documentation/services-web-api#/default/point>
+#[derive(Clone, Debug, Deserialize)]
+struct Points {
+    properties: PointProperties,
+}
 
-    /// Get the latest forecast. If the forecast is missing or outdated, spawn
-    /// a task to re-fetch it
-    pub fn forecast(&self) -> Option<Forecast> {
-        self.fetcher.data()
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PointProperties {
+    /// Forecast office
+    grid_id: String,
+    grid_x: u32,
+    grid_y: u32,
+    forecast: String,
+    forecast_hourly: String,
+    /// Lists stations near the gridpoint
+    observation_stations: String,
+}
+
+impl PointProperties {
+    /// Build the URLs for a known gridpoint, the same way NWS would
+    fn gridpoint(office: &str, x: u32, y: u32) -> Self {
+        let gridpoint =
+            format!("{}/gridpoints/{office}/{x},{y}", Weather::API_HOST);
+        Self {
+            grid_id: office.to_owned(),
+            grid_x: x,
+            grid_y: y,
+            forecast: format!("{gridpoint}/forecast"),
+            forecast_hourly: format!("{gridpoint}/forecast/hourly"),
+            observation_stations: format!("{gridpoint}/stations"),
+        }
     }
 }
 
-///https://www.weather.gov/documentation/services-web-api#/default/gridpoint_forecast
+/// <https://www.weather.gov/documentation/services-web-api#/default/gridpoint_stations>
+#[derive(Clone, Debug, Deserialize)]
+struct Stations {
+    features: Vec<Station>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Station {
+    properties: StationProperties,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
+struct StationProperties {
+    /// e.g. `KBOS`
+    station_identifier: String,
+}
+
+impl Stations {
+    /// ID of the nearest station. NWS sorts the list by distance
+    fn nearest(&self) -> Option<String> {
+        let station = self.features.first()?;
+        Some(station.properties.station_identifier.clone())
+    }
+}
+
+/// <https://www.weather.gov/documentation/services-web-api#/default/station_observation_latest>
+#[derive(Clone, Debug, Deserialize)]
+pub struct Observation {
+    properties: ObservationProperties,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ObservationProperties {
+    timestamp: DateTime<Utc>,
+    temperature: Measurement,
+}
+
+/// A measured value, with units given as a WMO unit code
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Measurement {
+    unit_code: String,
+    /// `None` if it wasn't measured or forecast
+    value: Option<f64>,
+}
+
+impl Observation {
+    /// Formatted temperature, in °F. `None` if the station didn't report one
+    pub fn temperature(&self) -> Option<String> {
+        let fahrenheit = self.properties.temperature.fahrenheit()?;
+        Some(format!("{fahrenheit:.0}°"))
+    }
+}
+
+impl Measurement {
+    /// Convert a temperature to °F. Stations report in °C
+    fn fahrenheit(&self) -> Option<f64> {
+        let value = self.value?;
+        match self.unit_code.as_str() {
+            "wmoUnit:degC" => Some(value * 9.0 / 5.0 + 32.0),
+            "wmoUnit:degF" => Some(value),
+            _ => None,
+        }
+    }
+}
+
+///https://www.weather.gov/documentation/services-web-api#/default/gridpoint_forecast
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Forecast {
     properties: ForecastProperties,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ForecastProperties {
     periods: Vec<ForecastPeriod>,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ForecastPeriod {
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     temperature: i32,
     probability_of_precipitation: Unit,
+    // Everything below is just extra detail, so a missing or odd value
+    // shouldn't cost us the whole forecast
+    #[serde(default)]
+    is_daytime: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    relative_humidity: Option<Unit>,
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    dewpoint: Option<Measurement>,
+    #[serde(default, deserialize_with = "deserialize_wind_speed")]
+    wind_speed: Option<WindSpeed>,
+    #[serde(default)]
+    wind_direction: Option<CompassDirection>,
+    /// e.g. `Mostly Sunny`
+    #[serde(default)]
+    short_forecast: String,
+    /// URL of an image for the conditions
+    #[serde(default)]
+    icon: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Unit {
     pub value: Option<i32>,
 }
 
 impl Forecast {
-    /// Get the current forecast period
-    pub fn now(&self) -> &ForecastPeriod {
-        &self.properties.periods[0]
+    /// Get the forecast period that contains the given time. If the forecast
+    /// doesn't cover it, fall back to the first period. `None` if there are no
+    /// periods at all
+    pub fn current(&self, now: DateTime<Utc>) -> Option<&ForecastPeriod> {
+        self.properties.periods.get(self.current_index(now))
     }
 
-    /// Get the list of periods that should be shown in the list. This skips
-    /// periods in the middle of the night.
-    pub fn future_periods(&self) -> impl '_ + Iterator<Item = &ForecastPeriod> {
+    /// Get the list of periods after the current one that should be shown in
+    /// the list. This skips periods in the middle of the night, in the given
+    /// timezone.
+    pub fn future_periods(
+        &self,
+        now: DateTime<Utc>,
+        timezone: LocalTz,
+    ) -> impl '_ + Iterator<Item = &ForecastPeriod> {
         let day_range = Weather::DAY_START..=Weather::DAY_END;
         self.properties
             .periods
             .iter()
-            .skip(1)
+            .skip(self.current_index(now) + 1)
             .step_by(Weather::PERIOD_INTERNAL)
             .filter(move |period| {
-                day_range.contains(&period.start_time().time())
+                day_range.contains(&period.start_time(timezone).time())
             })
     }
+
+    /// Index of the period that contains the given time, or 0 if none does
+    fn current_index(&self, now: DateTime<Utc>) -> usize {
+        self.properties
+            .periods
+            .iter()
+            .position(|period| {
+                period.start_time <= now && now < period.end_time
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl ForecastPeriod {
     /// Localized timestamp for the start of this period
-    pub fn start_time(&self) -> DateTime<Local> {
-        self.start_time.with_timezone(&Local)
+    pub fn start_time(&self, timezone: LocalTz) -> DateTime<FixedOffset> {
+        timezone.localize(self.start_time)
     }
 
     /// Formatted temperature
@@ -106,11 +644,165 @@ impl ForecastPeriod {
             self.probability_of_precipitation.value.unwrap_or_default()
         )
     }
+
+    /// Is this period during the day? `None` if NWS didn't say
+    pub fn is_daytime(&self) -> Option<bool> {
+        self.is_daytime
+    }
+
+    /// Formatted relative humidity. `None` if it's not forecast
+    pub fn humidity(&self) -> Option<String> {
+        Some(format!("{:.0}%", self.relative_humidity.as_ref()?.value?))
+    }
+
+    /// Formatted dewpoint, in °F. `None` if it's not forecast
+    pub fn dewpoint(&self) -> Option<String> {
+        Some(format!("{:.0}°", self.dewpoint.as_ref()?.fahrenheit()?))
+    }
+
+    /// Formatted wind direction and speed, e.g. `SW 5-10mph`. `None` if the
+    /// speed isn't forecast
+    pub fn wind(&self) -> Option<String> {
+        let speed = self.wind_speed?;
+        let wind = match self.wind_direction {
+            _ if speed.high == 0 => "Calm".to_owned(),
+            Some(direction) => format!("{direction} {speed}"),
+            None => speed.to_string(),
+        };
+        Some(wind)
+    }
+
+    pub fn short_forecast(&self) -> &str {
+        &self.short_forecast
+    }
+
+    pub fn icon(&self) -> &str {
+        &self.icon
+    }
+}
+
+/// Wind speed in mph. Longer periods get a range, e.g. `5 to 10 mph`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+struct WindSpeed {
+    low: u32,
+    high: u32,
+}
+
+impl WindSpeed {
+    /// Parse NWS's text format. `None` if it's not something we recognize
+    fn parse(value: &str) -> Option<Self> {
+        let speed = value.strip_suffix(" mph")?;
+        let (low, high) = speed.split_once(" to ").unwrap_or((speed, speed));
+        Some(Self {
+            low: low.parse().ok()?,
+            high: high.parse().ok()?,
+        })
+    }
+}
+
+/// Deserialize a value, or `None` if it's malformed
+fn deserialize_lenient<'de, D, T>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|value| serde_json::from_value(value).ok()))
+}
+
+/// Deserialize a wind speed, throwing it away if it can't be parsed
+fn deserialize_wind_speed<'de, D>(
+    deserializer: D,
+) -> Result<Option<WindSpeed>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.as_deref().and_then(WindSpeed::parse))
+}
+
+impl Display for WindSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.low == self.high {
+            write!(f, "{}mph", self.high)
+        } else {
+            write!(f, "{}-{}mph", self.low, self.high)
+        }
+    }
+}
+
+/// Direction the wind is coming from, on a 16-point compass
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum CompassDirection {
+    N,
+    Nne,
+    Ne,
+    Ene,
+    E,
+    Ese,
+    Se,
+    Sse,
+    S,
+    Ssw,
+    Sw,
+    Wsw,
+    W,
+    Wnw,
+    Nw,
+    Nnw,
+    /// No single direction, e.g. when the wind is calm
+    #[serde(other)]
+    Variable,
+}
+
+impl Display for CompassDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self {
+            Self::N => "N",
+            Self::Nne => "NNE",
+            Self::Ne => "NE",
+            Self::Ene => "ENE",
+            Self::E => "E",
+            Self::Ese => "ESE",
+            Self::Se => "SE",
+            Self::Sse => "SSE",
+            Self::S => "S",
+            Self::Ssw => "SSW",
+            Self::Sw => "SW",
+            Self::Wsw => "WSW",
+            Self::W => "W",
+            Self::Wnw => "WNW",
+            Self::Nw => "NW",
+            Self::Nnw => "NNW",
+            Self::Variable => "VRB",
+        };
+        write!(f, "{direction}")
+    }
+}
+
+/// NWS identifies clients by User-Agent, and wants contact info in it
+fn user_agent(config: &Config) -> String {
+    let app = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+    match &config.nws_contact {
+        Some(contact) => format!("{app} ({contact})"),
+        None => {
+            warn!("No nws_contact configured; NWS may block requests");
+            app.to_owned()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        config::test_config,
+        http::{Stub, StubTransport},
+        util::fixture_now,
+    };
 
     fn period(
         time: &str,
@@ -127,6 +819,13 @@ mod tests {
             probability_of_precipitation: Unit {
                 value: Some(probability_of_precipitation),
             },
+            is_daytime: Some(true),
+            relative_humidity: None,
+            dewpoint: None,
+            wind_speed: None,
+            wind_direction: None,
+            short_forecast: "Sunny".into(),
+            icon: String::new(),
         }
     }
 
@@ -142,6 +841,226 @@ mod tests {
             },
         };
 
-        assert_eq!(forecast.now(), &period("2024-05-24T17:00:00Z", 1, 84, 1));
+        assert_eq!(
+            forecast.current(fixture_now()),
+            Some(&period("2024-05-24T17:00:00Z", 1, 84, 1))
+        );
+
+        // --now picks the period it falls in
+        let current = |now: &str| forecast.current(now.parse().unwrap());
+        assert_eq!(
+            current("2024-05-24T18:30:00Z"),
+            Some(&period("2024-05-24T18:00:00Z", 1, 85, 0))
+        );
+        // Outside the forecast
+        assert_eq!(
+            current("2024-05-24T20:00:00Z"),
+            Some(&period("2024-05-24T17:00:00Z", 1, 84, 1))
+        );
+        let empty = Forecast {
+            properties: ForecastProperties {
+                periods: Vec::new(),
+            },
+        };
+        assert_eq!(empty.current(fixture_now()), None);
+    }
+
+    /// The period list starts after whichever period is current
+    #[test]
+    fn test_future_periods() {
+        let forecast = Forecast {
+            properties: ForecastProperties {
+                periods: (0..10)
+                    .map(|hour| {
+                        period(
+                            &format!("2024-05-24T{:02}:00:00Z", 14 + hour),
+                            1,
+                            80 + hour,
+                            0,
+                        )
+                    })
+                    .collect(),
+            },
+        };
+        let future = |now: &str| -> Vec<String> {
+            forecast
+                .future_periods(now.parse().unwrap(), test_config().timezone)
+                .map(ForecastPeriod::temperature)
+                .collect()
+        };
+
+        assert_eq!(future("2024-05-24T14:05:00Z"), ["81°", "85°", "89°"]);
+        assert_eq!(future("2024-05-24T17:05:00Z"), ["84°", "88°"]);
+    }
+
+    #[test]
+    fn test_period_fields() {
+        let forecast: Forecast = serde_json::from_str(include_str!(
+            "../fixtures/forecast_hourly.json"
+        ))
+        .unwrap();
+        let now = forecast.current(fixture_now()).unwrap();
+        assert_eq!(now.wind().as_deref(), Some("SW 7mph"));
+        assert_eq!(now.humidity().as_deref(), Some("52%"));
+        assert_eq!(now.dewpoint().as_deref(), Some("60°"));
+        assert_eq!(now.short_forecast(), "Sunny");
+        assert_eq!(now.is_daytime(), Some(true));
+
+        let period = |wind_speed: &str, wind_direction: &str| {
+            serde_json::from_value::<ForecastPeriod>(serde_json::json!({
+                "startTime": "2024-05-24T17:00:00Z",
+                "endTime": "2024-05-24T18:00:00Z",
+                "temperature": 84,
+                "probabilityOfPrecipitation": {"value": 1},
+                "windSpeed": wind_speed,
+                "windDirection": wind_direction,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            period("5 to 10 mph", "NNE").wind().as_deref(),
+            Some("NNE 5-10mph")
+        );
+        assert_eq!(period("0 mph", "").wind().as_deref(), Some("Calm"));
+        // Odd values are dropped, rather than failing the whole forecast
+        let odd = period("10 km/h", "nowhere");
+        assert_eq!(odd.wind(), None);
+        assert_eq!(odd.humidity(), None);
+        assert_eq!(odd.dewpoint(), None);
+        assert_eq!(odd.is_daytime(), None);
+        let odd: ForecastPeriod = serde_json::from_value(serde_json::json!({
+            "startTime": "2024-05-24T17:00:00Z",
+            "endTime": "2024-05-24T18:00:00Z",
+            "temperature": 84,
+            "probabilityOfPrecipitation": {"value": 1},
+            "relativeHumidity": {"value": "muggy"},
+            "dewpoint": {"value": 15.6},
+        }))
+        .unwrap();
+        assert_eq!(odd.humidity(), None);
+        assert_eq!(odd.dewpoint(), None);
+    }
+
+    #[test]
+    fn test_user_agent() {
+        let mut config = test_config();
+        config.nws_contact = Some("me@example.com".into());
+        assert_eq!(
+            user_agent(&config),
+            format!(
+                "goldfinger/{} (me@example.com)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    /// Latitude and longitude are resolved to a gridpoint, unless the
+    /// gridpoint is given explicitly
+    #[test]
+    fn test_resolve_location() {
+        let mut config = test_config();
+        config.latitude = Some(42.39641);
+        config.longitude = Some(-71.12261);
+        // Skip the station lookup
+        config.observation_station = Some("KBOS".into());
+        let observation_url =
+            "https://api.weather.gov/stations/KBOS/observations/latest";
+        let alerts_url =
+            "https://api.weather.gov/alerts/active?point=42.3964,-71.1226";
+        let forecast_url =
+            "https://api.weather.gov/gridpoints/BOX/70,93/forecast/hourly";
+        let points = format!(
+            r#"{{
+                "properties": {{
+                    "gridId": "BOX",
+                    "gridX": 70,
+                    "gridY": 93,
+                    "forecast": "https://example.com/forecast",
+                    "forecastHourly": "{forecast_url}",
+                    "observationStations": "https://example.com/stations"
+                }}
+            }}"#
+        );
+
+        // Explicit gridpoint wins
+        let transport = StubTransport::new([
+            Stub::ok(include_str!("../fixtures/forecast_hourly.json")),
+            Stub::ok(include_str!("../fixtures/forecast.json")),
+            Stub::ok(include_str!("../fixtures/observation.json")),
+            Stub::ok(include_str!("../fixtures/weather_alerts.json")),
+        ]);
+        let weather =
+            Weather::new(&config, Arc::new(transport.clone())).unwrap();
+        weather.fetch_blocking().unwrap();
+        assert_eq!(
+            transport.requests(),
+            [
+                "https://api.weather.gov/gridpoints/BOX/71,90/forecast/hourly",
+                "https://api.weather.gov/gridpoints/BOX/71,90/forecast",
+                observation_url,
+                alerts_url
+            ]
+        );
+
+        config.forecast_office = None;
+        config.forecast_gridpoint = None;
+        let transport = StubTransport::new([
+            Stub::ok(points),
+            Stub::ok(include_str!("../fixtures/forecast_hourly.json")),
+            Stub::ok(include_str!("../fixtures/forecast.json")),
+            Stub::ok(include_str!("../fixtures/observation.json")),
+            Stub::ok(include_str!("../fixtures/weather_alerts.json")),
+        ]);
+        let weather =
+            Weather::new(&config, Arc::new(transport.clone())).unwrap();
+        weather.fetch_blocking().unwrap();
+        assert_eq!(
+            transport.requests(),
+            [
+                "https://api.weather.gov/points/42.3964,-71.1226",
+                forecast_url,
+                "https://example.com/forecast",
+                observation_url,
+                alerts_url
+            ]
+        );
+        assert!(weather.forecast().is_some());
+
+        config.latitude = None;
+        assert!(Weather::new(&config, Arc::new(transport)).is_err());
+    }
+
+    /// Current conditions come from the nearest station, until the
+    /// observation gets too old
+    #[test]
+    fn test_observation() {
+        let mut config = test_config();
+        // No alerts
+        config.latitude = None;
+        let transport = StubTransport::new([
+            Stub::ok(include_str!("../fixtures/stations.json")),
+            Stub::ok(include_str!("../fixtures/forecast_hourly.json")),
+            Stub::ok(include_str!("../fixtures/forecast.json")),
+            Stub::ok(include_str!("../fixtures/observation.json")),
+        ]);
+        let weather =
+            Weather::new(&config, Arc::new(transport.clone())).unwrap();
+        weather.fetch_blocking().unwrap();
+        assert_eq!(
+            transport.requests(),
+            [
+                "https://api.weather.gov/gridpoints/BOX/71,90/stations",
+                "https://api.weather.gov/gridpoints/BOX/71,90/forecast/hourly",
+                "https://api.weather.gov/gridpoints/BOX/71,90/forecast",
+                "https://api.weather.gov/stations/KBOS/observations/latest",
+            ]
+        );
+
+        // Observed at 16:54
+        let now = "2024-05-24T17:05:00Z".parse().unwrap();
+        let observation = weather.observation(now).unwrap();
+        assert_eq!(observation.temperature().as_deref(), Some("82°"));
+        let later = "2024-05-24T18:00:00Z".parse().unwrap();
+        assert!(weather.observation(later).is_none());
     }
 }
@@ -0,0 +1,169 @@
+//! Active watches and warnings from NWS, for the configured location
+
+use crate::util::LocalTz;
+use chrono::{DateTime, Timelike, Utc};
+use serde::Deserialize;
+
+/// <https://www.weather.gov/documentation/services-web-api#/default/alerts_active>
+#[derive(Clone, Debug, Deserialize)]
+pub struct Alerts {
+    features: Vec<Feature>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Feature {
+    properties: Alert,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Alert {
+    /// e.g. `Winter Storm Warning`
+    event: String,
+    /// `Actual` for real alerts. There are also tests, exercises, etc.
+    status: String,
+    severity: Severity,
+    urgency: Urgency,
+    /// When the hazard is expected to end. `None` if NWS doesn't know yet
+    ends: Option<DateTime<Utc>>,
+    /// When this alert will be replaced or dropped. Stands in for `ends`
+    /// when that's missing
+    expires: DateTime<Utc>,
+}
+
+/// Ordered most to least severe
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum Severity {
+    Extreme,
+    Severe,
+    Moderate,
+    Minor,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Ordered most to least urgent
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum Urgency {
+    Immediate,
+    Expected,
+    Future,
+    Past,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Alerts {
+    /// Label for the most pressing watch or warning in effect, e.g.
+    /// `WINTER STORM WARNING\nuntil 7PM`. Advisories, statements, etc. aren't
+    /// worth taking over the screen for. Times are in the given timezone
+    pub fn label(
+        &self,
+        now: DateTime<Utc>,
+        timezone: LocalTz,
+    ) -> Option<String> {
+        let alert = self
+            .features
+            .iter()
+            .map(|feature| &feature.properties)
+            .filter(|alert| alert.is_watch_or_warning() && now < alert.end())
+            .min_by_key(|alert| (alert.severity, alert.urgency))?;
+        Some(alert.label(now, timezone))
+    }
+}
+
+impl Alert {
+    fn is_watch_or_warning(&self) -> bool {
+        self.status == "Actual"
+            && (self.event.ends_with("Warning")
+                || self.event.ends_with("Watch"))
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        self.ends.unwrap_or(self.expires)
+    }
+
+    /// Event name, then when it ends. The day is only included if it's not
+    /// today
+    fn label(&self, now: DateTime<Utc>, timezone: LocalTz) -> String {
+        let end = timezone.localize(self.end());
+        let day = if end.date_naive() == timezone.localize(now).date_naive() {
+            ""
+        } else {
+            "%a "
+        };
+        let time = if end.minute() == 0 {
+            "%-I%p"
+        } else {
+            "%-I:%M%p"
+        };
+        format!(
+            "{}\nuntil {}",
+            self.event.to_uppercase(),
+            end.format(&format!("{day}{time}"))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::test_config, util::fixture_now};
+
+    #[test]
+    fn test_label() {
+        let now = fixture_now();
+        let alerts: Alerts = serde_json::from_value(serde_json::json!({
+            "features": [
+                alert("Heat Advisory", "Moderate", "Expected", "19:00"),
+                alert("Flood Watch", "Severe", "Future", "23:00"),
+                alert("Severe Thunderstorm Warning", "Severe", "Immediate", "19:30"),
+                // Over already
+                alert("Tornado Warning", "Extreme", "Immediate", "12:00"),
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            alerts.label(now, test_config().timezone).as_deref(),
+            Some("SEVERE THUNDERSTORM WARNING\nuntil 7:30PM")
+        );
+
+        let mut test =
+            alert("Tornado Warning", "Extreme", "Immediate", "19:00");
+        test["properties"]["status"] = "Test".into();
+        let mut overnight = alert("Flood Watch", "Severe", "Future", "23:00");
+        overnight["properties"]["ends"] = "2024-05-25T02:00:00-04:00".into();
+        let alerts: Alerts = serde_json::from_value(serde_json::json!({
+            "features": [test, overnight]
+        }))
+        .unwrap();
+        assert_eq!(
+            alerts.label(now, test_config().timezone).as_deref(),
+            Some("FLOOD WATCH\nuntil Sat 2AM")
+        );
+
+        let alerts: Alerts = serde_json::from_value(serde_json::json!({
+            "features": [alert("Heat Advisory", "Moderate", "Expected", "19:00")]
+        }))
+        .unwrap();
+        assert_eq!(alerts.label(now, test_config().timezone), None);
+    }
+
+    /// Alert that ends at a time today
+    fn alert(
+        event: &str,
+        severity: &str,
+        urgency: &str,
+        ends: &str,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "properties": {
+                "event": event,
+                "status": "Actual",
+                "severity": severity,
+                "urgency": urgency,
+                "ends": format!("2024-05-24T{ends}:00-04:00"),
+                "expires": "2024-05-24T18:00:00-04:00",
+            }
+        })
+    }
+}
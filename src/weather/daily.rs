@@ -0,0 +1,155 @@
+//! Day and night forecast periods, for a high/low summary of each day
+
+use crate::util::LocalTz;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+
+/// <https://www.weather.gov/documentation/services-web-api#/default/gridpoint_forecast>
+#[derive(Clone, Debug, Deserialize)]
+pub struct DailyForecast {
+    properties: DailyProperties,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DailyProperties {
+    periods: Vec<DailyPeriod>,
+}
+
+/// A daytime (6am-6pm) or nighttime (6pm-6am) period
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DailyPeriod {
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    is_daytime: bool,
+    /// High for daytime periods, low for nighttime
+    temperature: i32,
+    /// e.g. `Chance Showers And Thunderstorms`
+    short_forecast: String,
+}
+
+/// High, low, and conditions for a single day
+#[derive(Clone, Debug, PartialEq)]
+pub struct DaySummary {
+    /// `Today` or `Tomorrow`
+    pub label: &'static str,
+    /// `Tdy` or `Tmw`, for when space is tight
+    pub short_label: &'static str,
+    /// `None` once the daytime period is over
+    pub high: Option<i32>,
+    /// Low for the night after the day. `None` if it's not forecast yet
+    pub low: Option<i32>,
+    pub short_forecast: String,
+}
+
+impl DailyForecast {
+    /// Summarize today and tomorrow, as days go in the given timezone.
+    /// Periods that are already over are skipped, so late in the day, today
+    /// may only have a low
+    pub fn days(
+        &self,
+        now: DateTime<Utc>,
+        timezone: LocalTz,
+    ) -> Vec<DaySummary> {
+        let today = timezone.localize(now).date_naive();
+        [
+            ("Today", "Tdy", Some(today)),
+            ("Tomorrow", "Tmw", today.succ_opt()),
+        ]
+        .into_iter()
+        .filter_map(|(label, short_label, date)| {
+            let date = date?;
+            // A night period is part of the day it starts on
+            let periods: Vec<&DailyPeriod> = self
+                .properties
+                .periods
+                .iter()
+                .filter(|period| {
+                    now < period.end_time
+                        && timezone.localize(period.start_time).date_naive()
+                            == date
+                })
+                .collect();
+            let day = periods.iter().find(|period| period.is_daytime);
+            let night = periods.iter().find(|period| !period.is_daytime);
+            Some(DaySummary {
+                label,
+                short_label,
+                high: day.map(|period| period.temperature),
+                low: night.map(|period| period.temperature),
+                short_forecast: day.or(night)?.short_forecast.clone(),
+            })
+        })
+        .collect()
+    }
+}
+
+impl DaySummary {
+    /// e.g. `78°/61°`
+    pub fn high_low(&self) -> String {
+        let temperature = |temperature: Option<i32>| match temperature {
+            Some(temperature) => format!("{temperature}°"),
+            None => "--".to_owned(),
+        };
+        format!("{}/{}", temperature(self.high), temperature(self.low))
+    }
+}
+
+impl Display for DaySummary {
+    /// e.g. `Today 78°/61° Showers`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.label,
+            self.high_low(),
+            self.short_forecast
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_days() {
+        let forecast: DailyForecast =
+            serde_json::from_str(include_str!("../../fixtures/forecast.json"))
+                .unwrap();
+        let days = |now: &str| -> Vec<String> {
+            forecast
+                .days(now.parse().unwrap(), test_config().timezone)
+                .iter()
+                .map(DaySummary::to_string)
+                .collect()
+        };
+
+        assert_eq!(
+            days("2024-05-24T13:05:00-04:00"),
+            [
+                "Today 85°/64° Sunny",
+                "Tomorrow 83°/62° Chance Showers And Thunderstorms"
+            ]
+        );
+        // The afternoon is over
+        assert_eq!(
+            days("2024-05-24T19:00:00-04:00"),
+            [
+                "Today --/64° Mostly Clear",
+                "Tomorrow 83°/62° Chance Showers And Thunderstorms"
+            ]
+        );
+        let short_labels: Vec<&str> = forecast
+            .days(
+                "2024-05-24T19:00:00-04:00".parse().unwrap(),
+                test_config().timezone,
+            )
+            .iter()
+            .map(|day| day.short_label)
+            .collect();
+        assert_eq!(short_labels, ["Tdy", "Tmw"]);
+    }
+}